@@ -0,0 +1,176 @@
+use std::error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The steps of an atomic write, in order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Step {
+    /// Writing the contents to the temporary file
+    Write,
+    /// Flushing the temporary file to disk
+    Sync,
+    /// Moving the temporary file over the target
+    Rename,
+    /// Flushing the directory entry to disk
+    SyncDir
+}
+
+/// Error flushing the directory after the file was already replaced: it holds
+/// the new contents, but they might not survive a crash.
+#[derive(Debug)]
+struct DirectoryNotSynced(io::Error);
+
+impl fmt::Display for DirectoryNotSynced {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "saved, but not flushed to disk: {}", self.0)
+    }
+}
+
+impl error::Error for DirectoryNotSynced {}
+
+/// Whether an error from [`write_atomically`] came after the file was replaced,
+/// so that it holds the new contents in spite of the error.
+pub(crate) fn contents_replaced(err: &io::Error) -> bool {
+    return err.get_ref().is_some_and(|inner| inner.is::<DirectoryNotSynced>());
+}
+
+/// Replaces the file at `path` with `contents`, so that after a crash at any point the
+/// file holds either the old contents or the new ones, never a mix. The contents are
+/// written to a temporary file next to it, flushed to disk, and renamed over it.
+/// The file keeps its permissions.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    return write_atomically_with(path, contents, |_| Ok(()));
+}
+
+/// [`write_atomically`], calling `before` ahead of each step so that tests can interrupt it.
+pub(crate) fn write_atomically_with<F>(path: &Path, contents: &[u8], mut before: F) -> io::Result<()>
+where
+    F: FnMut(Step) -> io::Result<()>
+{
+    let temp_path = temp_path_for(path);
+    let result = write_and_rename(path, &temp_path, contents, &mut before);
+    if result.is_err() {
+        // the target is untouched, only the temporary file needs tidying up
+        let _ = fs::remove_file(&temp_path);
+    }
+    return result;
+}
+
+fn write_and_rename<F>(path: &Path, temp_path: &Path, contents: &[u8], before: &mut F) -> io::Result<()>
+where
+    F: FnMut(Step) -> io::Result<()>
+{
+    let mut temp_file = OpenOptions::new().write(true).create_new(true).open(temp_path)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        temp_file.set_permissions(metadata.permissions())?;
+    }
+
+    before(Step::Write)?;
+    temp_file.write_all(contents)?;
+    before(Step::Sync)?;
+    temp_file.sync_all()?;
+    drop(temp_file);
+
+    before(Step::Rename)?;
+    fs::rename(temp_path, path)?;
+    return before(Step::SyncDir).and_then(|_| sync_dir(path))
+        .map_err(|err| io::Error::other(DirectoryNotSynced(err)));
+}
+
+/// A path in the same directory as `path`, so that renaming it over `path` is atomic.
+fn temp_path_for(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let unique = format!(".{}.{}.{}.tmp", name, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    return path.with_file_name(unique);
+}
+
+/// Makes sure a rename in the directory of `path` survives a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    return File::open(dir)?.sync_all();
+}
+
+/// Directories can't be opened as files on other platforms, where renames are flushed with the file.
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::atomic::{contents_replaced, write_atomically, write_atomically_with, Step};
+    use rstest::rstest;
+    use std::fs;
+    use std::io;
+
+    fn interrupted() -> io::Error {
+        return io::Error::other("interrupted");
+    }
+
+    #[rstest]
+    fn replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        write_atomically(&path, b"old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[rstest]
+    #[case(Step::Write)]
+    #[case(Step::Sync)]
+    #[case(Step::Rename)]
+    fn interrupted_write_keeps_old_contents(#[case] step: Step) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        write_atomically(&path, b"old").unwrap();
+
+        let result = write_atomically_with(&path, b"new", |current| {
+            if current == step { Err(interrupted()) } else { Ok(()) }
+        });
+
+        assert!(!contents_replaced(&result.err().unwrap()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[rstest]
+    fn unsynced_directory_still_has_new_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        write_atomically(&path, b"old").unwrap();
+
+        let result = write_atomically_with(&path, b"new", |current| {
+            if current == Step::SyncDir { Err(interrupted()) } else { Ok(()) }
+        });
+
+        assert!(contents_replaced(&result.err().unwrap()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        write_atomically(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
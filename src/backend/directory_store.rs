@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::backend::{atomic, LedgerStore, LedgerLock, Result, RevisionConflict, DEFAULT_LOCK_TIMEOUT};
+use crate::core::currency::Currency;
+use crate::core::event::Event;
+use crate::core::rates::RateTable;
+use crate::core::recurring::Recurring;
+use crate::core::transaction::Transaction;
+use crate::core::user::{User, UserId};
+use crate::Ledger;
+
+const METADATA_FILE: &str = "ledger.json";
+const TRANSACTIONS_DIR: &str = "transactions";
+
+/// Keeps a ledger in a directory: the users and everything else which isn't
+/// a transaction in `ledger.json`, and each transaction, along with its edits,
+/// in a file of its own under `transactions/`. New transactions always go in
+/// new files with random names, so transactions added in different places at
+/// once, e.g. in a synced folder, never overwrite each other. Balances are
+/// worked out again on reading. The ledger's revision is that of `ledger.json`,
+/// which only changes when something other than a transaction does; a
+/// transaction's file is only saved if it still holds the events it was read with.
+pub struct DirectoryStore {
+    dir_path: PathBuf,
+    lock_timeout: Duration
+}
+
+/// Everything in a ledger except its transactions.
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    revision: u64,
+    users: Vec<User>,
+    #[serde(default)]
+    base_currency: Currency,
+    #[serde(default, skip_serializing_if = "RateTable::is_empty")]
+    rates: RateTable,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recurring: Vec<Recurring>
+}
+
+/// A transaction's file, with the events which added and then edited it.
+#[derive(Serialize, Deserialize)]
+struct TransactionFile {
+    /// Name of the file of the transaction this one undoes, which unlike its id never changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reverses: Option<String>,
+    events: Vec<Event>
+}
+
+/// A transaction as read from its file.
+struct StoredTransaction {
+    name: String,
+    id: usize,
+    /// Whether the transaction was given another id than the one in its file
+    renumbered: bool,
+    file: TransactionFile
+}
+
+impl Metadata {
+    fn of(ledger: &Ledger, revision: u64) -> Metadata {
+        let mut users: Vec<User> = ledger.get_users().into_iter().cloned().collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        return Metadata {
+            revision,
+            users,
+            base_currency: ledger.get_base_currency().clone(),
+            rates: ledger.get_rates().clone(),
+            recurring: ledger.get_recurring().clone()
+        };
+    }
+
+    /// Events which set up everything but the transactions as it is now.
+    fn into_events(self) -> Vec<Event> {
+        let mut events: Vec<Event> = self.users.into_iter().map(|user| Event::UserAdded { user }).collect();
+        if self.base_currency != Currency::default() {
+            events.push(Event::BaseCurrencySet { currency: self.base_currency });
+        }
+        if !self.rates.is_empty() {
+            events.push(Event::RatesImported { entries: self.rates.entries() });
+        }
+        events.extend(self.recurring.into_iter().map(|recurring| Event::RecurringAdded { recurring }));
+        return events;
+    }
+}
+
+impl DirectoryStore {
+    pub fn new(path: &Path) -> DirectoryStore {
+        return DirectoryStore { dir_path: path.to_owned(), lock_timeout: DEFAULT_LOCK_TIMEOUT };
+    }
+
+    /// Sets how long to wait for another process to finish with the ledger.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> DirectoryStore {
+        self.lock_timeout = timeout;
+        return self;
+    }
+
+    fn metadata_path(&self) -> PathBuf {
+        return self.dir_path.join(METADATA_FILE);
+    }
+
+    fn transactions_path(&self) -> PathBuf {
+        return self.dir_path.join(TRANSACTIONS_DIR);
+    }
+
+    fn transaction_path(&self, name: &str) -> PathBuf {
+        return self.transactions_path().join(format!("{}.json", name));
+    }
+
+    fn read_metadata(&self) -> Result<Option<Metadata>> {
+        return match fs::read_to_string(self.metadata_path()) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into())
+        };
+    }
+
+    /// The stored transactions, in order of their ids.
+    fn read_transactions(&self) -> Result<Vec<StoredTransaction>> {
+        let entries = match fs::read_dir(self.transactions_path()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into())
+        };
+
+        let mut transactions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let file: TransactionFile = serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            let id = file.events.first().and_then(Event::transaction).map(|transaction| transaction.id)
+                .ok_or_else(|| format!("{}: no transaction in file", path.display()))?;
+            transactions.push(StoredTransaction { name, id, renumbered: false, file });
+        }
+        transactions.sort_by(|a, b| (a.id, &a.name).cmp(&(b.id, &b.name)));
+
+        // transactions added in two places at once can have the same id: the one whose file name
+        // comes first keeps it, and the others are given new ones, which the next save writes down
+        let mut next_id = transactions.last().map_or(1, |transaction| transaction.id + 1);
+        let mut taken = HashSet::new();
+        for transaction in &mut transactions {
+            if !taken.insert(transaction.id) {
+                transaction.id = next_id;
+                transaction.renumbered = true;
+                next_id += 1;
+            }
+        }
+        transactions.sort_by_key(|transaction| transaction.id);
+
+        let ids: HashMap<String, usize> = transactions.iter()
+            .map(|transaction| (transaction.name.clone(), transaction.id)).collect();
+        for stored in &mut transactions {
+            let reverses = stored.file.reverses.as_ref().and_then(|name| ids.get(name)).copied();
+            for transaction in stored.file.events.iter_mut().filter_map(Event::transaction_mut) {
+                transaction.id = stored.id;
+                if reverses.is_some() {
+                    transaction.reverses = reverses;
+                }
+            }
+        }
+        return Ok(transactions);
+    }
+
+    fn write_transaction(&self, name: &str, file: &TransactionFile) -> Result<()> {
+        let path = self.transaction_path(name);
+        return Ok(atomic::write_atomically(&path, serde_json::to_string_pretty(file)?.as_bytes())?);
+    }
+}
+
+/// Events which add back, inactive, the users who were removed in one place while transactions
+/// were added for them in another, so that every transaction can still be counted.
+fn removed_users(users: &[User], transactions: &[StoredTransaction]) -> Vec<Event> {
+    let mut known: HashSet<&UserId> = users.iter().map(|user| &user.id).collect();
+    let mut events = Vec::new();
+    let involved = transactions.iter()
+        .flat_map(|stored| &stored.file.events)
+        .filter_map(Event::transaction)
+        .flat_map(Transaction::users);
+    for id in involved {
+        if known.insert(id) {
+            events.push(Event::UserAdded { user: User { id: id.clone(), name: id.clone(), active: false } });
+        }
+    }
+    return events;
+}
+
+/// Events as JSON, to tell whether two lists of them are the same.
+fn events_json<'a>(events: impl Iterator<Item = &'a Event>) -> Result<Vec<serde_json::Value>> {
+    return events.map(|event| Ok(serde_json::to_value(event)?)).collect();
+}
+
+impl LedgerStore for DirectoryStore {
+    fn read(&self) -> Result<Ledger> {
+        let metadata = match self.read_metadata()? {
+            Some(metadata) => metadata,
+            None => return Err(format!("no such ledger: {}", self.dir_path.display()).into())
+        };
+        let transactions = self.read_transactions()?;
+        let revision = metadata.revision;
+        let removed = removed_users(&metadata.users, &transactions);
+        let mut events = metadata.into_events();
+        events.extend(removed);
+        for stored in transactions {
+            events.extend(stored.file.events);
+        }
+
+        let mut ledger = Ledger::from_events(events)?;
+        ledger.mark_saved(revision);
+        return Ok(ledger);
+    }
+
+    fn save(&self, ledger: &mut Ledger, read_at: u64) -> Result<()> {
+        let stored = self.read_metadata()?.map(|metadata| metadata.revision);
+        if stored.is_some() && read_at == 0 {
+            return Err(format!("{} already exists", self.dir_path.display()).into());
+        }
+
+        let saved_count = ledger.events().len() - ledger.unsaved_events().len();
+        let saved_events = &ledger.events()[..saved_count];
+        let mut changed_ids = Vec::new();
+        let mut metadata_changed = false;
+        for event in ledger.unsaved_events() {
+            match event.transaction() {
+                Some(transaction) if !changed_ids.contains(&transaction.id) => changed_ids.push(transaction.id),
+                Some(_) => (),
+                None => metadata_changed = true
+            }
+        }
+
+        let stored = stored.unwrap_or(0);
+        if metadata_changed && stored != read_at {
+            return Err(RevisionConflict { read_at, stored }.into());
+        }
+
+        // each changed transaction's file has to hold the events the ledger was read with, or none for
+        // a new one, unless it already holds the new ones from an earlier save which didn't get to finish
+        let stored_transactions = self.read_transactions()?;
+        let by_id: HashMap<usize, &StoredTransaction> = stored_transactions.iter()
+            .map(|transaction| (transaction.id, transaction)).collect();
+        for id in &changed_ids {
+            let of_transaction = |event: &&Event| event.transaction().is_some_and(|transaction| transaction.id == *id);
+            let read: Vec<&Event> = saved_events.iter().filter(of_transaction).collect();
+            let found = events_json(by_id.get(id).into_iter().flat_map(|transaction| &transaction.file.events))?;
+            if found != events_json(read.iter().copied())?
+                && found != events_json(ledger.events().iter().filter(of_transaction))? {
+                return Err(RevisionConflict { read_at: read.len() as u64, stored: found.len() as u64 }.into());
+            }
+        }
+
+        // transactions are written first, so that the revision only goes up once everything else is saved
+        fs::create_dir_all(self.transactions_path())?;
+        for transaction in stored_transactions.iter().filter(|transaction| transaction.renumbered) {
+            self.write_transaction(&transaction.name, &transaction.file)?;
+        }
+        let mut names: HashMap<usize, String> = stored_transactions.into_iter()
+            .map(|transaction| (transaction.id, transaction.name)).collect();
+        for id in changed_ids {
+            let events: Vec<Event> = ledger.events().iter()
+                .filter(|event| event.transaction().is_some_and(|transaction| transaction.id == id))
+                .cloned().collect();
+            let reverses = events.first().and_then(Event::transaction).and_then(|transaction| transaction.reverses)
+                .and_then(|reversed| names.get(&reversed).cloned());
+            let name = names.entry(id).or_insert_with(|| Uuid::new_v4().to_string()).clone();
+            self.write_transaction(&name, &TransactionFile { reverses, events })?;
+        }
+
+        let mut revision = read_at;
+        if metadata_changed {
+            revision = read_at + 1;
+            let metadata_str = serde_json::to_string_pretty(&Metadata::of(ledger, revision))?;
+            atomic::write_atomically(&self.metadata_path(), metadata_str.as_bytes())?;
+        }
+        ledger.mark_saved(revision);
+        return Ok(());
+    }
+
+    fn lock(&self) -> Result<LedgerLock> {
+        return LedgerLock::acquire(&self.dir_path, self.lock_timeout);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{Amount, Ledger};
+    use crate::backend::{DirectoryStore, LedgerStore, RevisionConflict};
+    use crate::core::transaction::TransactionPatch;
+    use chrono::{TimeZone, Utc};
+    use rstest::rstest;
+    use std::fs;
+    use std::path::Path;
+
+    fn transaction_files(dir: &Path) -> usize {
+        return fs::read_dir(dir.join("transactions")).unwrap().count();
+    }
+
+    fn saved_ledger(store: &DirectoryStore) -> Ledger {
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        store.save(&mut ledger, 0).unwrap();
+        return store.read().unwrap();
+    }
+
+    #[rstest]
+    fn each_transaction_has_its_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        let mut ledger = saved_ledger(&store);
+
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        ledger.add_transfer("Frodo", "Bilbo", Amount::from_major(2), "", None).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+        assert_eq!(transaction_files(&dir.path().join("ledger")), 2);
+
+        let mut read = store.read().unwrap();
+        assert_eq!(read.revision(), 1);
+        assert_eq!(read.get_balances(), ledger.get_balances());
+
+        let patch = TransactionPatch { description: Some(String::from("rent")), ..Default::default() };
+        read.edit_transaction(1, patch).unwrap();
+        store.save(&mut read, 1).unwrap();
+        assert_eq!(transaction_files(&dir.path().join("ledger")), 2);
+
+        let read = store.read().unwrap();
+        assert_eq!(read.get_transactions()[0].description, "rent");
+        assert_eq!(read.get_revisions(1).len(), 1);
+    }
+
+    /// Copies the transaction files from one copy of a ledger to another, as syncing them would.
+    fn sync_transactions(from: &Path, to: &Path) {
+        for entry in fs::read_dir(from.join("transactions")).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, to.join("transactions").join(path.file_name().unwrap())).unwrap();
+        }
+    }
+
+    #[rstest]
+    fn transactions_added_in_two_places_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (here, there) = (dir.path().join("here"), dir.path().join("there"));
+        let store = DirectoryStore::new(&here);
+        let mut ledger = saved_ledger(&store);
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(1), "", None).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+        fs::create_dir_all(there.join("transactions")).unwrap();
+        fs::copy(here.join("ledger.json"), there.join("ledger.json")).unwrap();
+        sync_transactions(&here, &there);
+
+        let mut first = store.read().unwrap();
+        first.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        store.save(&mut first, 1).unwrap();
+        let other_store = DirectoryStore::new(&there);
+        let mut second = other_store.read().unwrap();
+        second.add_transfer("Bilbo", "Frodo", Amount::from_major(3), "", None).unwrap();
+        second.reverse_by_id(2).unwrap();
+        other_store.save(&mut second, 1).unwrap();
+        sync_transactions(&there, &here);
+
+        let mut ledger = store.read().unwrap();
+        let mut ids: Vec<usize> = ledger.get_transactions().iter().map(|t| t.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(ledger.get_balances()[&ledger.find_user("Bilbo").unwrap().id], Amount::from_major(6));
+
+        // the undo still belongs to the transaction it undid, whatever id that ended up with
+        let reversal = ledger.get_transactions().iter().find(|t| t.reverses.is_some()).unwrap();
+        let reversed = ledger.get_transactions().iter().find(|t| Some(t.id) == reversal.reverses).unwrap();
+        assert_eq!(reversed.total_spending(), Amount::from_major(3));
+
+        // once saved, the new ids stay the same however the files are read
+        let numbered: Vec<(usize, Amount)> = ledger.get_transactions().iter().map(|t| (t.id, t.total_spending())).collect();
+        ledger.add_user("Sam", false).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+        assert!(store.read_transactions().unwrap().iter().all(|transaction| !transaction.renumbered));
+        let read = store.read().unwrap();
+        let renumbered: Vec<(usize, Amount)> = read.get_transactions().iter().map(|t| (t.id, t.total_spending())).collect();
+        assert_eq!(renumbered, numbered);
+    }
+
+    #[rstest]
+    fn transaction_added_elsewhere_since_reading_is_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        saved_ledger(&store);
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        second.add_transfer("Bilbo", "Frodo", Amount::from_major(3), "", None).unwrap();
+        store.save(&mut first, 1).unwrap();
+
+        let err = store.save(&mut second, 1).err().unwrap();
+        assert!(err.downcast_ref::<RevisionConflict>().is_some());
+        assert_eq!(transaction_files(&dir.path().join("ledger")), 1);
+    }
+
+    #[rstest]
+    fn stale_edit_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        let mut ledger = saved_ledger(&store);
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.edit_transaction(1, TransactionPatch { description: Some(String::from("rent")), ..Default::default() }).unwrap();
+        second.edit_transaction(1, TransactionPatch { description: Some(String::from("food")), ..Default::default() }).unwrap();
+        store.save(&mut first, 1).unwrap();
+
+        let err = store.save(&mut second, 1).err().unwrap();
+        let conflict = err.downcast_ref::<RevisionConflict>().unwrap();
+        assert_eq!((conflict.read_at, conflict.stored), (1, 2));
+        assert_eq!(store.read().unwrap().get_transactions()[0].description, "rent");
+    }
+
+    #[rstest]
+    fn transaction_for_removed_user_is_still_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        let mut ledger = saved_ledger(&store);
+        ledger.add_user("Sam", false).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        let sam = first.find_user("Sam").unwrap().id.clone();
+        first.remove_user("Sam").unwrap();
+        second.add_transfer("Bilbo", "Sam", Amount::from_major(5), "", None).unwrap();
+        store.save(&mut first, 2).unwrap();
+        store.save(&mut second, 2).unwrap();
+
+        let ledger = store.read().unwrap();
+        assert!(!ledger.get_user(&sam).unwrap().active);
+        assert_eq!(ledger.get_balances()[&sam], Amount::from_major(-5));
+    }
+
+    #[rstest]
+    fn unfinished_save_can_be_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        saved_ledger(&store);
+        let metadata_path = dir.path().join("ledger").join("ledger.json");
+        let metadata = fs::read_to_string(&metadata_path).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut retried = store.read().unwrap();
+        let time = Some(Utc.ymd(2024, 5, 1).and_hms(12, 0, 0));
+        for ledger in [&mut first, &mut retried] {
+            ledger.rename_user("Frodo", "Frodo Baggins").unwrap();
+            ledger.add_transfer("Bilbo", "Frodo Baggins", Amount::from_major(2), "", time).unwrap();
+        }
+        // as if saving had stopped after the transactions, before the users were written
+        store.save(&mut first, 1).unwrap();
+        fs::write(&metadata_path, metadata).unwrap();
+        assert_eq!(store.read().unwrap().revision(), 1);
+
+        store.save(&mut retried, 1).unwrap();
+        assert_eq!(transaction_files(&dir.path().join("ledger")), 1);
+        let ledger = store.read().unwrap();
+        assert_eq!(ledger.revision(), 2);
+        assert!(ledger.find_user("Frodo Baggins").is_ok());
+    }
+
+    #[rstest]
+    fn stale_metadata_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DirectoryStore::new(&dir.path().join("ledger"));
+        saved_ledger(&store);
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.add_user("Sam", false).unwrap();
+        second.add_user("Merry", false).unwrap();
+        store.save(&mut first, 1).unwrap();
+
+        let err = store.save(&mut second, 1).err().unwrap();
+        assert!(err.downcast_ref::<RevisionConflict>().is_some());
+        assert!(store.save(&mut Ledger::new(vec!["Sam"]), 0).is_err());
+    }
+}
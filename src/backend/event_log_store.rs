@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+
+use crate::backend::{LedgerStore, LedgerLock, Result, RevisionConflict, DEFAULT_LOCK_TIMEOUT};
+use crate::core::event::Event;
+use crate::Ledger;
+
+/// Keeps a ledger as the log of events which made it, one JSON object per
+/// line. Saving only ever appends the new events to the end of the file,
+/// and balances and other derived values are worked out again on reading.
+/// The ledger's revision is the number of events in the log. An unfinished
+/// last line, left by a save which was cut short, is ignored on reading and
+/// dropped by the next save.
+pub struct EventLogStore {
+    file_path: PathBuf,
+    lock_timeout: Duration
+}
+
+impl EventLogStore {
+    pub fn new(path: &Path) -> EventLogStore {
+        return EventLogStore { file_path: path.to_owned(), lock_timeout: DEFAULT_LOCK_TIMEOUT };
+    }
+
+    /// Sets how long to wait for another process to finish with the ledger.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> EventLogStore {
+        self.lock_timeout = timeout;
+        return self;
+    }
+
+    /// The events in the log, along with the part of the file they were read from.
+    /// A save cut short, e.g. by a crash, can leave the last line unfinished:
+    /// if it can't be read it is left out, as though that save never happened.
+    fn read_log(&self) -> Result<(Vec<Event>, String)> {
+        let mut contents = match fs::read_to_string(&self.file_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok((Vec::new(), String::new())),
+            Err(err) => return Err(err.into())
+        };
+        let mut events = Vec::new();
+        let mut read_len = 0;
+        for (line_no, line) in contents.split_inclusive('\n').enumerate() {
+            if !line.trim().is_empty() {
+                match serde_json::from_str(line) {
+                    Ok(event) => events.push(event),
+                    Err(_) if !line.ends_with('\n') => break,
+                    Err(err) => return Err(format!("{} line {}: {}", self.file_path.display(), line_no + 1, err).into())
+                }
+            }
+            read_len += line.len();
+        }
+        contents.truncate(read_len);
+        return Ok((events, contents));
+    }
+}
+
+impl LedgerStore for EventLogStore {
+    fn read(&self) -> Result<Ledger> {
+        if !self.file_path.exists() {
+            return Err(format!("no such ledger: {}", self.file_path.display()).into());
+        }
+        return Ok(Ledger::from_events(self.read_log()?.0)?);
+    }
+
+    fn save(&self, ledger: &mut Ledger, read_at: u64) -> Result<()> {
+        let (events, read) = self.read_log()?;
+        let stored = events.len() as u64;
+        if stored > 0 && read_at == 0 {
+            return Err(format!("{} already exists", self.file_path.display()).into());
+        }
+        if stored != read_at {
+            return Err(RevisionConflict { read_at, stored }.into());
+        }
+
+        let mut lines = String::new();
+        if !read.is_empty() && !read.ends_with('\n') {
+            lines.push('\n');
+        }
+        for event in ledger.unsaved_events() {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(&self.file_path)?;
+        // drops whatever is left of an unfinished line, so the new events start on a line of their own
+        file.set_len(read.len() as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_all()?;
+        ledger.mark_saved(stored + ledger.unsaved_events().len() as u64);
+        return Ok(());
+    }
+
+    fn lock(&self) -> Result<LedgerLock> {
+        return LedgerLock::acquire(&self.file_path, self.lock_timeout);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::Ledger;
+    use crate::Amount;
+    use crate::backend::{EventLogStore, LedgerStore, RevisionConflict};
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    fn saving_appends_new_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let store = EventLogStore::new(&path);
+
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        store.save(&mut ledger, 0).unwrap();
+        let first_save = fs::read_to_string(&path).unwrap();
+
+        let mut ledger = store.read().unwrap();
+        assert_eq!(ledger.revision(), 2);
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        store.save(&mut ledger, 2).unwrap();
+        assert_eq!(ledger.revision(), 3);
+        let second_save = fs::read_to_string(&path).unwrap();
+
+        assert!(second_save.starts_with(&first_save));
+        assert_eq!(second_save.lines().count(), 3);
+        assert!(!second_save.contains("balances"));
+
+        let ledger = store.read().unwrap();
+        let bilbo = &ledger.find_user("Bilbo").unwrap().id;
+        assert_eq!(ledger.get_balances()[bilbo], Amount::from_major(5));
+    }
+
+    #[rstest]
+    fn stale_ledger_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let store = EventLogStore::new(&path);
+        store.save(&mut Ledger::new(vec!["Bilbo", "Frodo"]), 0).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.add_user("Sam", false).unwrap();
+        second.add_user("Merry", false).unwrap();
+        store.save(&mut first, 2).unwrap();
+
+        let err = store.save(&mut second, 2).err().unwrap();
+        assert!(err.downcast_ref::<RevisionConflict>().is_some());
+        assert!(store.read().unwrap().find_user("Merry").is_err());
+    }
+
+    #[rstest]
+    fn unfinished_last_line_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let store = EventLogStore::new(&path);
+        store.save(&mut Ledger::new(vec!["Bilbo", "Frodo"]), 0).unwrap();
+
+        // as left by a save cut short halfway through a line
+        let mut contents = fs::read_to_string(&path).unwrap();
+        contents.push_str("{\"TransactionAdded\":{\"transa");
+        fs::write(&path, contents).unwrap();
+
+        let mut ledger = store.read().unwrap();
+        assert_eq!(ledger.revision(), 2);
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        store.save(&mut ledger, 2).unwrap();
+
+        let ledger = store.read().unwrap();
+        assert_eq!(ledger.revision(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(ledger.get_transactions().len(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 use std::error;
+use std::fmt;
 use std::result;
 
 use crate::core::Ledger;
+use crate::backend::LedgerLock;
 
 pub type BackendError = Box<dyn error::Error>;
 
@@ -9,5 +11,39 @@ pub type Result<T> = result::Result<T, BackendError>;
 
 pub trait LedgerStore {
     fn read(&self) -> Result<Ledger>;
-    fn save(&self, ledger: &Ledger) -> Result<()>;
+
+    /// Saves the ledger, which was read at revision `read_at`, as the next revision.
+    /// Fails with [`RevisionConflict`] if the stored ledger has moved on since.
+    /// The check is only reliable while holding the [`LedgerStore::lock`].
+    fn save(&self, ledger: &mut Ledger, read_at: u64) -> Result<()>;
+
+    /// Keeps other processes from changing the ledger until the lock is dropped.
+    fn lock(&self) -> Result<LedgerLock>;
+
+    /// Reads the ledger, makes `change` to it and saves it, holding the
+    /// lock throughout so that no change made meanwhile is lost.
+    fn update(&self, change: &mut dyn FnMut(&mut Ledger) -> Result<()>) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut ledger = self.read()?;
+        let read_at = ledger.revision();
+        change(&mut ledger)?;
+        return self.save(&mut ledger, read_at);
+    }
+}
+
+/// Occurs when saving a ledger which was changed by someone else after it was read.
+/// Read it again and redo the change, rather than overwriting theirs.
+#[derive(Debug)]
+pub struct RevisionConflict {
+    pub read_at: u64,
+    pub stored: u64
+}
+
+impl fmt::Display for RevisionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the ledger was changed elsewhere since it was read (revision {} is stored, {} was read)",
+            self.stored, self.read_at)
+    }
 }
+
+impl error::Error for RevisionConflict {}
@@ -1,16 +1,70 @@
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::fs;
+use std::io::{self, ErrorKind};
 
-use crate::backend::{LedgerStore, Result};
+use serde::Deserialize;
+
+use crate::backend::{LedgerStore, LedgerLock, Result, RevisionConflict, DEFAULT_LOCK_TIMEOUT};
+use crate::backend::atomic::{self, Step};
+use crate::core::ledger::first_revision;
 use crate::Ledger;
 
 pub struct JsonStore {
-    file_path: PathBuf
+    file_path: PathBuf,
+    lock_timeout: Duration
 }
 
 impl JsonStore {
     pub fn new(path: &Path) -> JsonStore {
-        return JsonStore { file_path: path.to_owned() };
+        return JsonStore { file_path: path.to_owned(), lock_timeout: DEFAULT_LOCK_TIMEOUT };
+    }
+
+    /// Sets how long to wait for another process to finish with the ledger.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> JsonStore {
+        self.lock_timeout = timeout;
+        return self;
+    }
+
+    fn save_with<F>(&self, ledger: &mut Ledger, read_at: u64, before: F) -> Result<()>
+    where
+        F: FnMut(Step) -> io::Result<()>
+    {
+        let stored = match self.stored_revision()? {
+            Some(_) if read_at == 0 => return Err(format!("{} already exists", self.file_path.display()).into()),
+            Some(stored) => stored,
+            None => 0
+        };
+        if stored != read_at {
+            return Err(RevisionConflict { read_at, stored }.into());
+        }
+
+        // the file has the new revision, but the ledger only moves on to it once the file is written
+        let saved_at = ledger.revision();
+        ledger.set_revision(read_at + 1);
+        let ledger_str = serde_json::to_string_pretty(ledger);
+        ledger.set_revision(saved_at);
+        let written = atomic::write_atomically_with(&self.file_path, ledger_str?.as_bytes(), before);
+        // a file which was replaced but not flushed holds the new revision all the same
+        if written.as_ref().map_or_else(atomic::contents_replaced, |_| true) {
+            ledger.mark_saved(read_at + 1);
+        }
+        return Ok(written?);
+    }
+
+    /// Revision of the ledger in the file, if there is one.
+    fn stored_revision(&self) -> Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct Stored {
+            #[serde(default = "first_revision")]
+            revision: u64
+        }
+
+        return match fs::read_to_string(&self.file_path) {
+            Ok(contents) => Ok(Some(serde_json::from_str::<Stored>(&contents)?.revision)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into())
+        };
     }
 }
 
@@ -21,9 +75,145 @@ impl LedgerStore for JsonStore {
             .map_err(|err| err.into());
     }
 
-    fn save(&self, ledger: &Ledger) -> Result<()> {
-        let ledger_str = serde_json::to_string_pretty(ledger)?;
-        fs::write(&self.file_path, ledger_str)?;
-        return Ok(());
+    /// Replaces the file in one step, so a crash part way through leaves the previous ledger.
+    fn save(&self, ledger: &mut Ledger, read_at: u64) -> Result<()> {
+        return self.save_with(ledger, read_at, |_| Ok(()));
+    }
+
+    fn lock(&self) -> Result<LedgerLock> {
+        return LedgerLock::acquire(&self.file_path, self.lock_timeout);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{Amount, Ledger};
+    use crate::backend::{JsonStore, LedgerStore, LockTimeout, RevisionConflict};
+    use crate::backend::atomic::Step;
+    use rstest::rstest;
+    use std::fs;
+    use std::io;
+    use std::thread;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case(Step::Write)]
+    #[case(Step::Sync)]
+    #[case(Step::Rename)]
+    fn interrupted_save_keeps_previous_ledger(#[case] step: Step) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonStore::new(&dir.path().join("ledger.json"));
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        store.save(&mut ledger, 0).unwrap();
+
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        let result = store.save_with(&mut ledger, 1, |current| {
+            if current == step { Err(io::Error::other("disk full")) } else { Ok(()) }
+        });
+
+        assert!(result.is_err());
+        assert_eq!(ledger.revision(), 1);
+        assert_eq!(ledger.unsaved_events().len(), 1);
+        let saved = store.read().unwrap();
+        assert!(saved.get_transactions().is_empty());
+        assert_eq!(saved.get_users().len(), 2);
+    }
+
+    #[rstest]
+    fn unsynced_save_is_still_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonStore::new(&dir.path().join("ledger.json"));
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        store.save(&mut ledger, 0).unwrap();
+
+        ledger.add_transfer("Bilbo", "Frodo", Amount::from_major(5), "", None).unwrap();
+        let result = store.save_with(&mut ledger, 1, |current| {
+            if current == Step::SyncDir { Err(io::Error::other("disk gone")) } else { Ok(()) }
+        });
+
+        assert!(result.is_err());
+        assert_eq!(ledger.revision(), 2);
+        assert!(ledger.unsaved_events().is_empty());
+        assert_eq!(store.read().unwrap().get_transactions().len(), 1);
+
+        ledger.add_user("Sam", false).unwrap();
+        store.save(&mut ledger, 2).unwrap();
+    }
+
+    #[rstest]
+    fn concurrent_updates_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        JsonStore::new(&path).save(&mut Ledger::new(vec!["Bilbo"]), 0).unwrap();
+
+        let workers: Vec<_> = (0..8).map(|i| {
+            let path = path.clone();
+            thread::spawn(move || {
+                JsonStore::new(&path).update(&mut |ledger| {
+                    ledger.add_user(&format!("Hobbit {}", i), false)?;
+                    Ok(())
+                }).unwrap();
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let ledger = JsonStore::new(&path).read().unwrap();
+        assert_eq!(ledger.get_users().len(), 9);
+        assert_eq!(ledger.revision(), 9);
+    }
+
+    #[rstest]
+    fn stale_ledger_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonStore::new(&dir.path().join("ledger.json"));
+        store.save(&mut Ledger::new(vec!["Bilbo", "Frodo"]), 0).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.add_user("Sam", false).unwrap();
+        second.add_user("Merry", false).unwrap();
+        store.save(&mut first, 1).unwrap();
+        assert_eq!(first.revision(), 2);
+
+        let err = store.save(&mut second, 1).err().unwrap();
+        let conflict = err.downcast_ref::<RevisionConflict>().unwrap();
+        assert_eq!((conflict.read_at, conflict.stored), (1, 2));
+        assert!(store.read().unwrap().find_user("Merry").is_err());
+    }
+
+    #[rstest]
+    fn existing_ledger_is_not_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        let store = JsonStore::new(&path);
+        store.save(&mut Ledger::new(vec!["Bilbo", "Frodo"]), 0).unwrap();
+        assert!(store.save(&mut Ledger::new(vec!["Sam"]), 0).is_err());
+
+        // a file from before revisions were kept is still there to be replaced by mistake
+        let mut legacy: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        legacy.remove("revision");
+        fs::write(&path, serde_json::to_string(&legacy).unwrap()).unwrap();
+        assert!(store.save(&mut Ledger::new(vec!["Sam"]), 0).is_err());
+
+        let mut ledger = store.read().unwrap();
+        assert_eq!(ledger.revision(), 1);
+        ledger.add_user("Sam", false).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+        assert_eq!(store.read().unwrap().get_users().len(), 3);
+    }
+
+    #[rstest]
+    fn update_times_out_while_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonStore::new(&dir.path().join("ledger.json")).with_lock_timeout(Duration::from_millis(100));
+        store.save(&mut Ledger::new(vec!["Bilbo"]), 0).unwrap();
+
+        let _lock = store.lock().unwrap();
+        let err = store.update(&mut |_| Ok(())).err().unwrap();
+        assert!(err.downcast_ref::<LockTimeout>().is_some());
     }
 }
@@ -0,0 +1,81 @@
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::Result;
+
+/// How long to wait by default for another process to let go of a ledger.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An advisory lock on a ledger, held until it is dropped. It is taken on a separate
+/// `.lock` file next to the ledger, as the ledger itself is replaced when it is saved.
+pub struct LedgerLock {
+    _file: File
+}
+
+impl LedgerLock {
+    /// Takes the lock for the ledger at `ledger_path`, waiting up
+    /// to `timeout` for another process holding it to let go.
+    pub fn acquire(ledger_path: &Path, timeout: Duration) -> Result<LedgerLock> {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock_path_for(ledger_path))?;
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(LedgerLock { _file: file }),
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => thread::sleep(RETRY_INTERVAL),
+                Err(TryLockError::WouldBlock) => {
+                    return Err(LockTimeout { path: ledger_path.to_owned(), timeout }.into());
+                },
+                Err(TryLockError::Error(err)) => return Err(err.into())
+            }
+        }
+    }
+}
+
+fn lock_path_for(ledger_path: &Path) -> PathBuf {
+    let name = ledger_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    return ledger_path.with_file_name(format!(".{}.lock", name));
+}
+
+/// Occurs when another process held on to a ledger for longer than we were prepared to wait.
+#[derive(Debug)]
+pub struct LockTimeout {
+    pub path: PathBuf,
+    pub timeout: Duration
+}
+
+impl fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is being changed by another process, gave up waiting after {}s",
+            self.path.display(), self.timeout.as_secs_f32())
+    }
+}
+
+impl error::Error for LockTimeout {}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::lock::{LedgerLock, LockTimeout};
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    fn lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.json");
+        let timeout = Duration::from_millis(100);
+
+        let lock = LedgerLock::acquire(&path, timeout).unwrap();
+        let err = LedgerLock::acquire(&path, timeout).err().unwrap();
+        assert!(err.downcast_ref::<LockTimeout>().is_some());
+
+        drop(lock);
+        assert!(LedgerLock::acquire(&path, timeout).is_ok());
+    }
+}
@@ -1,5 +1,17 @@
 mod json_store;
+mod event_log_store;
+mod directory_store;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
 mod interface;
+mod atomic;
+mod lock;
 
-pub use interface::{LedgerStore, Result, BackendError};
-pub use json_store::JsonStore;
\ No newline at end of file
+pub use interface::{LedgerStore, Result, BackendError, RevisionConflict};
+pub use json_store::JsonStore;
+pub use event_log_store::EventLogStore;
+pub use directory_store::DirectoryStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteStore;
+pub use atomic::write_atomically;
+pub use lock::{LedgerLock, LockTimeout, DEFAULT_LOCK_TIMEOUT};
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::backend::{LedgerStore, LedgerLock, Result, RevisionConflict, DEFAULT_LOCK_TIMEOUT};
+use crate::core::currency::Currency;
+use crate::core::event::Event;
+use crate::core::rates::RateEntry;
+use crate::core::recurring::Recurring;
+use crate::core::transaction::{AmountPerUser, Benefit, BenefitPerUser};
+use crate::core::user::{User, UserId};
+use crate::{Amount, Ledger, Transaction};
+
+/// Changes to the schema, oldest first. A database's `user_version`
+/// is the number of them which have been made to it.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE users (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        active INTEGER NOT NULL
+    );
+    CREATE TABLE rates (
+        currency TEXT NOT NULL,
+        date TEXT NOT NULL,
+        rate TEXT NOT NULL,
+        PRIMARY KEY (currency, date)
+    );
+    CREATE TABLE transactions (
+        id INTEGER PRIMARY KEY,
+        datetime TEXT NOT NULL,
+        is_direct INTEGER NOT NULL,
+        description TEXT NOT NULL,
+        remainder TEXT NOT NULL,
+        currency TEXT,
+        rate TEXT,
+        reverses INTEGER REFERENCES transactions (id),
+        category TEXT,
+        tags TEXT NOT NULL,
+        items TEXT NOT NULL,
+        surcharges TEXT NOT NULL
+    );
+    CREATE TABLE contributions (
+        transaction_id INTEGER NOT NULL REFERENCES transactions (id),
+        position INTEGER NOT NULL,
+        user_id TEXT NOT NULL REFERENCES users (id),
+        amount INTEGER NOT NULL,
+        PRIMARY KEY (transaction_id, position)
+    );
+    CREATE TABLE benefits (
+        transaction_id INTEGER NOT NULL REFERENCES transactions (id),
+        position INTEGER NOT NULL,
+        user_id TEXT NOT NULL REFERENCES users (id),
+        kind TEXT NOT NULL,
+        value INTEGER,
+        PRIMARY KEY (transaction_id, position)
+    );
+    CREATE INDEX contributions_by_user ON contributions (user_id);
+    CREATE INDEX benefits_by_user ON benefits (user_id);
+    CREATE TABLE revisions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        transaction_id INTEGER NOT NULL REFERENCES transactions (id),
+        edited_at TEXT NOT NULL,
+        previous TEXT NOT NULL
+    );
+    CREATE TABLE recurring (
+        id INTEGER PRIMARY KEY,
+        recurring TEXT NOT NULL
+    );",
+    "ALTER TABLE transactions ADD COLUMN subtotal INTEGER;"
+];
+
+/// Keeps a ledger in an SQLite database, with tables for the users,
+/// transactions and their contributions and benefits. Saving only writes
+/// the changes made since the ledger was read, and balances are worked out
+/// again on reading. The ledger's revision is kept in the `settings` table.
+pub struct SqliteStore {
+    file_path: PathBuf,
+    lock_timeout: Duration
+}
+
+/// A transaction's row, before its contributions and benefits are added.
+type TransactionRow = (i64, String, bool, String, String, Option<String>, Option<String>,
+    Option<i64>, Option<String>, String, String, String, Option<i64>);
+
+impl SqliteStore {
+    pub fn new(path: &Path) -> SqliteStore {
+        return SqliteStore { file_path: path.to_owned(), lock_timeout: DEFAULT_LOCK_TIMEOUT };
+    }
+
+    /// Sets how long to wait for another process to finish with the ledger.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> SqliteStore {
+        self.lock_timeout = timeout;
+        return self;
+    }
+
+    /// Opens the database, bringing its schema up to date.
+    fn connect(&self) -> Result<Connection> {
+        let mut conn = Connection::open(&self.file_path)?;
+        conn.busy_timeout(self.lock_timeout)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        return Ok(conn);
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: i64 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(format!("the ledger was written by a newer version (schema version {})", version).into());
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (applied + 1) as i64)?;
+    }
+    tx.commit()?;
+    return Ok(());
+}
+
+fn setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+    return Ok(conn.query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| row.get(0)).optional()?);
+}
+
+fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute("INSERT INTO settings (key, value) VALUES (?1, ?2)
+        ON CONFLICT (key) DO UPDATE SET value = excluded.value", [key, value])?;
+    return Ok(());
+}
+
+fn stored_revision(conn: &Connection) -> Result<u64> {
+    return Ok(match setting(conn, "revision")? {
+        Some(revision) => revision.parse()?,
+        None => 0
+    });
+}
+
+fn benefit_columns(benefit: &Benefit) -> (&'static str, Option<i64>) {
+    match benefit {
+        Benefit::Sum(amount) => ("sum", Some(amount.minor())),
+        Benefit::Even => ("even", None),
+        Benefit::Shares(weight) => ("shares", Some(*weight as i64)),
+        Benefit::Percent(pct) => ("percent", Some(*pct as i64))
+    }
+}
+
+fn benefit_from_columns(kind: &str, value: Option<i64>) -> Result<Benefit> {
+    return match (kind, value) {
+        ("sum", Some(minor)) => Ok(Benefit::Sum(Amount::from_minor(minor))),
+        ("even", _) => Ok(Benefit::Even),
+        ("shares", Some(weight)) => Ok(Benefit::Shares(weight.try_into()?)),
+        ("percent", Some(pct)) => Ok(Benefit::Percent(pct.try_into()?)),
+        _ => Err(format!("not a valid benefit: {} {:?}", kind, value).into())
+    };
+}
+
+/// Inserts the transaction, or replaces the one with the same id, along with its contributions and benefits.
+fn write_transaction(conn: &Connection, transaction: &Transaction) -> Result<()> {
+    conn.execute("INSERT INTO transactions (id, datetime, is_direct, description, remainder,
+            currency, rate, reverses, category, tags, items, surcharges, subtotal)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+        ON CONFLICT (id) DO UPDATE SET datetime = excluded.datetime, is_direct = excluded.is_direct,
+            description = excluded.description, remainder = excluded.remainder, currency = excluded.currency,
+            rate = excluded.rate, reverses = excluded.reverses, category = excluded.category,
+            tags = excluded.tags, items = excluded.items, surcharges = excluded.surcharges,
+            subtotal = excluded.subtotal",
+        params![
+            transaction.id as i64,
+            transaction.datetime.to_rfc3339(),
+            transaction.is_direct,
+            transaction.description,
+            serde_json::to_string(&transaction.remainder)?,
+            transaction.currency.as_ref().map(Currency::to_string),
+            transaction.rate.map(|rate| rate.to_string()),
+            transaction.reverses.map(|id| id as i64),
+            transaction.category,
+            serde_json::to_string(&transaction.tags)?,
+            serde_json::to_string(&transaction.items)?,
+            serde_json::to_string(&transaction.surcharges)?,
+            transaction.subtotal.map(|subtotal| subtotal.minor())
+        ])?;
+
+    let id = transaction.id as i64;
+    conn.execute("DELETE FROM contributions WHERE transaction_id = ?1", [id])?;
+    conn.execute("DELETE FROM benefits WHERE transaction_id = ?1", [id])?;
+    for (position, (user, amount)) in transaction.contributions().iter().enumerate() {
+        conn.execute("INSERT INTO contributions (transaction_id, position, user_id, amount) VALUES (?1, ?2, ?3, ?4)",
+            params![id, position as i64, user, amount.minor()])?;
+    }
+    for (position, (user, benefit)) in transaction.benefits().iter().enumerate() {
+        let (kind, value) = benefit_columns(benefit);
+        conn.execute("INSERT INTO benefits (transaction_id, position, user_id, kind, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, position as i64, user, kind, value])?;
+    }
+    return Ok(());
+}
+
+/// Every transaction as it is now, in order of id, or only the one with the given id.
+fn read_transactions(conn: &Connection, only: Option<usize>) -> Result<Vec<Transaction>> {
+    let only = only.map(|id| id as i64);
+    let mut contributions: HashMap<i64, AmountPerUser<UserId>> = HashMap::new();
+    let mut statement = conn.prepare("SELECT transaction_id, user_id, amount FROM contributions
+        WHERE ?1 IS NULL OR transaction_id = ?1 ORDER BY transaction_id, position")?;
+    let rows = statement.query_map([only], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)))?;
+    for row in rows {
+        let (id, user, minor) = row?;
+        contributions.entry(id).or_default().push((user, Amount::from_minor(minor)));
+    }
+
+    let mut benefits: HashMap<i64, BenefitPerUser<UserId>> = HashMap::new();
+    let mut statement = conn.prepare("SELECT transaction_id, user_id, kind, value FROM benefits
+        WHERE ?1 IS NULL OR transaction_id = ?1 ORDER BY transaction_id, position")?;
+    let rows = statement.query_map([only], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get(3)?))
+    })?;
+    for row in rows {
+        let (id, user, kind, value) = row?;
+        benefits.entry(id).or_default().push((user, benefit_from_columns(&kind, value)?));
+    }
+
+    let mut statement = conn.prepare("SELECT id, datetime, is_direct, description, remainder, currency, rate,
+        reverses, category, tags, items, surcharges, subtotal FROM transactions WHERE ?1 IS NULL OR id = ?1 ORDER BY id")?;
+    let rows = statement.query_map([only], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?,
+            row.get(7)?, row.get(8)?, row.get(9)?, row.get(10)?, row.get(11)?, row.get(12)?))
+    })?;
+
+    let mut transactions = Vec::new();
+    for row in rows {
+        let (id, datetime, is_direct, description, remainder, currency, rate,
+            reverses, category, tags, items, surcharges, subtotal): TransactionRow = row?;
+        let contributions = contributions.remove(&id).unwrap_or_default();
+        let benefits = benefits.remove(&id).unwrap_or_default();
+        let datetime = DateTime::parse_from_rfc3339(&datetime)?.with_timezone(&Utc);
+
+        let mut transaction = Transaction::new(
+            contributions.iter().map(|(user, amount)| (user.as_str(), *amount)).collect(),
+            benefits.iter().map(|(user, benefit)| (user.as_str(), *benefit)).collect(),
+            &description, is_direct, Some(id as usize), Some(datetime));
+        transaction.remainder = serde_json::from_str(&remainder)?;
+        transaction.currency = currency.map(|code| code.parse()).transpose()?;
+        transaction.rate = rate.map(|rate| rate.parse()).transpose()?;
+        transaction.reverses = reverses.map(|id| id as usize);
+        transaction.category = category;
+        transaction.tags = serde_json::from_str(&tags)?;
+        transaction.items = serde_json::from_str(&items)?;
+        transaction.surcharges = serde_json::from_str(&surcharges)?;
+        transaction.subtotal = subtotal.map(Amount::from_minor);
+        transactions.push(transaction);
+    }
+    return Ok(transactions);
+}
+
+fn read_transaction(conn: &Connection, id: usize) -> Result<Transaction> {
+    return read_transactions(conn, Some(id))?.pop().ok_or_else(|| format!("no such transaction id: {}", id).into());
+}
+
+/// Events which rebuild the ledger in the database, much like a snapshot:
+/// users are added as they are now, and each transaction is added followed by its edits.
+fn read_events(conn: &Connection) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+
+    let mut statement = conn.prepare("SELECT id, name, active FROM users ORDER BY rowid")?;
+    let users = statement.query_map([], |row| Ok(User { id: row.get(0)?, name: row.get(1)?, active: row.get(2)? }))?;
+    for user in users {
+        events.push(Event::UserAdded { user: user? });
+    }
+
+    if let Some(currency) = setting(conn, "base_currency")? {
+        events.push(Event::BaseCurrencySet { currency: currency.parse()? });
+    }
+
+    let mut statement = conn.prepare("SELECT date, currency, rate FROM rates ORDER BY currency, date")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let (date, currency, rate) = row?;
+        entries.push(RateEntry { date: date.parse()?, currency: currency.parse()?, rate: rate.parse()? });
+    }
+    if !entries.is_empty() {
+        events.push(Event::RatesImported { entries });
+    }
+
+    let mut revisions: HashMap<usize, Vec<(DateTime<Utc>, Transaction)>> = HashMap::new();
+    let mut statement = conn.prepare("SELECT transaction_id, edited_at, previous FROM revisions ORDER BY id")?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+    for row in rows {
+        let (id, edited_at, previous) = row?;
+        let edited_at = DateTime::parse_from_rfc3339(&edited_at)?.with_timezone(&Utc);
+        revisions.entry(id as usize).or_default().push((edited_at, serde_json::from_str(&previous)?));
+    }
+
+    for transaction in read_transactions(conn, None)? {
+        let edits = revisions.remove(&transaction.id).unwrap_or_default();
+        let mut versions = edits.iter().map(|(_, previous)| previous.clone()).chain(std::iter::once(transaction));
+        let first = versions.next().expect("there is always a current version");
+        events.push(match first.reverses {
+            Some(_) => Event::TransactionReversed { reversal: first },
+            None => Event::TransactionAdded { transaction: first }
+        });
+        for ((edited_at, _), version) in edits.iter().zip(versions) {
+            events.push(Event::TransactionEdited { edited_at: *edited_at, transaction: version });
+        }
+    }
+
+    let mut statement = conn.prepare("SELECT recurring FROM recurring ORDER BY id")?;
+    let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+    for row in rows {
+        let recurring: Recurring = serde_json::from_str(&row?)?;
+        events.push(Event::RecurringAdded { recurring });
+    }
+    return Ok(events);
+}
+
+/// Makes the change described by the event to the database.
+fn write_event(conn: &Connection, event: &Event) -> Result<()> {
+    match event {
+        Event::UserAdded { user } => {
+            conn.execute("INSERT INTO users (id, name, active) VALUES (?1, ?2, ?3)",
+                params![user.id, user.name, user.active])?;
+        },
+        Event::UserRenamed { id, name } => {
+            conn.execute("UPDATE users SET name = ?2 WHERE id = ?1", params![id, name])?;
+        },
+        Event::UserDeactivated { id } => {
+            conn.execute("UPDATE users SET active = 0 WHERE id = ?1", [id])?;
+        },
+        Event::UserReactivated { id } => {
+            conn.execute("UPDATE users SET active = 1 WHERE id = ?1", [id])?;
+        },
+        Event::UserRemoved { id } => {
+            conn.execute("DELETE FROM users WHERE id = ?1", [id])?;
+        },
+        Event::BaseCurrencySet { currency } => set_setting(conn, "base_currency", currency.code())?,
+        Event::RatesImported { entries } => {
+            for entry in entries {
+                conn.execute("INSERT INTO rates (currency, date, rate) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (currency, date) DO UPDATE SET rate = excluded.rate",
+                    params![entry.currency.code(), entry.date.to_string(), entry.rate.to_string()])?;
+            }
+        },
+        Event::TransactionAdded { transaction } | Event::TransactionReversed { reversal: transaction } => {
+            write_transaction(conn, transaction)?;
+        },
+        Event::TransactionEdited { edited_at, transaction } => {
+            let previous = read_transaction(conn, transaction.id)?;
+            conn.execute("INSERT INTO revisions (transaction_id, edited_at, previous) VALUES (?1, ?2, ?3)",
+                params![transaction.id as i64, edited_at.to_rfc3339(), serde_json::to_string(&previous)?])?;
+            write_transaction(conn, transaction)?;
+        },
+        Event::RecurringAdded { recurring } | Event::RecurringUpdated { recurring } => {
+            conn.execute("INSERT INTO recurring (id, recurring) VALUES (?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET recurring = excluded.recurring",
+                params![recurring.id as i64, serde_json::to_string(recurring)?])?;
+        }
+    }
+    return Ok(());
+}
+
+impl LedgerStore for SqliteStore {
+    fn read(&self) -> Result<Ledger> {
+        if !self.file_path.exists() {
+            return Err(format!("no such ledger: {}", self.file_path.display()).into());
+        }
+        let conn = self.connect()?;
+        let mut ledger = Ledger::from_events(read_events(&conn)?)?;
+        ledger.mark_saved(stored_revision(&conn)?);
+        return Ok(ledger);
+    }
+
+    fn save(&self, ledger: &mut Ledger, read_at: u64) -> Result<()> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let stored = stored_revision(&tx)?;
+        if stored > 0 && read_at == 0 {
+            return Err(format!("{} already exists", self.file_path.display()).into());
+        }
+        if stored != read_at {
+            return Err(RevisionConflict { read_at, stored }.into());
+        }
+
+        for event in ledger.unsaved_events() {
+            write_event(&tx, event)?;
+        }
+        set_setting(&tx, "revision", &(read_at + 1).to_string())?;
+        tx.commit()?;
+        ledger.mark_saved(read_at + 1);
+        return Ok(());
+    }
+
+    fn lock(&self) -> Result<LedgerLock> {
+        return LedgerLock::acquire(&self.file_path, self.lock_timeout);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{Amount, Ledger};
+    use crate::backend::{LedgerStore, RevisionConflict, SqliteStore};
+    use crate::core::currency::Currency;
+    use crate::core::receipt::{Item, Surcharge};
+    use crate::core::recurring::Schedule;
+    use crate::core::transaction::{Benefit, TransactionPatch};
+    use crate::Transaction;
+    use chrono::NaiveDate;
+    use rstest::rstest;
+    use rusqlite::Connection;
+
+    use super::MIGRATIONS;
+
+    fn assert_same_ledger(read: &Ledger, ledger: &Ledger) {
+        assert_eq!(read.get_balances(), ledger.get_balances());
+        assert_eq!(serde_json::to_value(read.get_transactions()).unwrap(),
+            serde_json::to_value(ledger.get_transactions()).unwrap());
+        assert_eq!(read.get_users().len(), ledger.get_users().len());
+        assert_eq!(read.get_base_currency(), ledger.get_base_currency());
+        assert_eq!(read.get_recurring().len(), ledger.get_recurring().len());
+    }
+
+    #[rstest]
+    fn saves_and_reads_back_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::new(&dir.path().join("ledger.sqlite"));
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo", "Sam", "Merry"]);
+        ledger.set_base_currency("EUR".parse::<Currency>().unwrap()).unwrap();
+        store.save(&mut ledger, 0).unwrap();
+
+        ledger.add_expense(vec![("Bilbo", Amount::from_major(30))],
+            vec![("Bilbo", Benefit::Even), ("Frodo", Benefit::Shares(2)), ("Sam", Benefit::Sum(Amount::from_major(3)))],
+            "dinner", None).unwrap();
+        let mut receipt = Transaction::itemized("Frodo",
+            vec![Item { description: String::from("ale"), price: Amount::from_major(8), beneficiaries: vec![] }],
+            vec![Surcharge::new("tip", "10%".parse().unwrap())], "", None);
+        receipt.items[0].beneficiaries = vec![ledger.find_user("Sam").unwrap().id.clone()];
+        ledger.add_transaction(receipt).unwrap();
+        ledger.add_transfer("Sam", "Frodo", Amount::from_major(2), "", None).unwrap();
+        ledger.reverse_by_id(3).unwrap();
+        let mut free = Transaction::new(vec![("Frodo", Amount::from_major(10))],
+            vec![("Bilbo", Benefit::Sum(Amount::from_major(10)))], "", false, None, None);
+        free.add_surcharges(vec![Surcharge::new("discount", "-100%".parse().unwrap())]);
+        ledger.add_transaction(free).unwrap();
+        store.save(&mut ledger, 1).unwrap();
+
+        let patch = TransactionPatch { description: Some(String::from("supper")), ..Default::default() };
+        ledger.edit_transaction(1, patch).unwrap();
+        ledger.rename_user("Merry", "Meriadoc").unwrap();
+        ledger.deactivate_user("Meriadoc").unwrap();
+        let start = NaiveDate::from_ymd_opt(2022, 6, 1).unwrap();
+        let rent = Transaction::new(vec![("Bilbo", Amount::from_major(100))], vec![("Frodo", Benefit::Even)],
+            "rent", false, None, None);
+        ledger.add_recurring(rent, Schedule::Monthly(1), start, None).unwrap();
+        assert_eq!(ledger.materialize_recurring(NaiveDate::from_ymd_opt(2022, 7, 15).unwrap()).created, 2);
+        store.save(&mut ledger, 2).unwrap();
+
+        let read = store.read().unwrap();
+        assert_eq!(read.revision(), 3);
+        assert_same_ledger(&read, &ledger);
+        assert_eq!(read.get_revisions(1).len(), 1);
+        assert!(!read.find_user("Meriadoc").unwrap().active);
+    }
+
+    #[rstest]
+    fn stale_ledger_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::new(&dir.path().join("ledger.sqlite"));
+        store.save(&mut Ledger::new(vec!["Bilbo", "Frodo"]), 0).unwrap();
+
+        let mut first = store.read().unwrap();
+        let mut second = store.read().unwrap();
+        first.add_user("Sam", false).unwrap();
+        second.add_user("Merry", false).unwrap();
+        store.save(&mut first, 1).unwrap();
+
+        let err = store.save(&mut second, 1).err().unwrap();
+        assert!(err.downcast_ref::<RevisionConflict>().is_some());
+        assert!(store.read().unwrap().find_user("Merry").is_err());
+    }
+
+    #[rstest]
+    fn newer_schema_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.sqlite");
+        let store = SqliteStore::new(&path);
+        store.save(&mut Ledger::new(vec!["Bilbo"]), 0).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+        conn.pragma_update(None, "user_version", version + 1).unwrap();
+
+        assert!(store.read().is_err());
+    }
+}
@@ -1,23 +1,43 @@
-use chrono::{Utc, TimeZone, DateTime};
-use divider::{Ledger, Amount,
-    backend::{LedgerStore, JsonStore},
-    transaction::{BenefitPerUser, Benefit, AmountPerUser, TransactionResult}};
+#![allow(clippy::needless_return)]
 
-use std::path::PathBuf;
+use chrono::{Utc, TimeZone, DateTime, NaiveDate};
+use divider::{Ledger, Amount, Transaction,
+    allocation::RemainderPolicy,
+    currency::{Currency, Rate},
+    rates::{self, RateEntry},
+    backend::{LedgerStore, JsonStore, EventLogStore, DirectoryStore},
+    query::Query,
+    receipt::{Item, Surcharge, SurchargeValue},
+    recurring::{RecurringRun, Schedule},
+    report::{self, Period, Spending},
+    settlement::Settlement,
+    transaction::{BenefitPerUser, Benefit, AmountPerUser, TransactionPatch, TransactionResult, UserAmountMap}};
+
+#[cfg(feature = "sqlite")]
+use divider::backend::SqliteStore;
+
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::error;
 use std::result;
 use std::process::ExitCode;
+use std::time::Duration;
 
 use colored::Colorize;
-use clap::{Args, Parser, Subcommand};
+use regex::Regex;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[clap(version, about, propagate_version = true)]
 struct Cli {
-   /// Path to ledger file to operate on
+   /// Path to ledger file to operate on. Files ending in `.jsonl` keep the log of all changes.
    #[clap(value_parser)]
     path: PathBuf,
 
+   /// Seconds to wait for another process to finish changing the ledger
+   #[clap(long, value_parser, default_value_t = 10)]
+   wait: u64,
+
    /// Action to perform
    #[clap(subcommand)]
    action: Subcommands,
@@ -29,22 +49,110 @@ enum Subcommands {
     New {
         /// Names of the users on the ledger
         #[clap(value_parser, required=true, min_values=1)]
-        names: Vec<String>
+        names: Vec<String>,
+
+        /// Currency balances are reported in, as a three letter code
+        #[clap(short='c', long, value_parser)]
+        currency: Option<Currency>
     },
     /// Read and display balances
-    Balances,
-    /// List all transactions
-    List,
+    Balances {
+        /// Show balances separately for each currency instead of converting them
+        #[clap(short='p', long, action)]
+        per_currency: bool,
+
+        /// Show balances as they were at this time. Example format: "2022-05-01 12:21"
+        #[clap(long, parse(try_from_str = parse_time_minutes), conflicts_with = "per-currency")]
+        at: Option<DateTime<Utc>>
+    },
+    /// List transactions, optionally only those matching all the given filters
+    List(ListFilter),
+    /// Show a table of how much each user paid and consumed in each period
+    Report {
+        /// Length of the periods to group spending by
+        #[clap(short='p', long, value_enum, default_value_t = PeriodArg::Month)]
+        period: PeriodArg
+    },
+    /// Show how much was spent on each category
+    Categories {
+        /// Show what each user consumed in each category instead of the totals
+        #[clap(short='u', long, action)]
+        per_user: bool
+    },
+    /// List all users with their ids
+    Users,
     /// Add a new user
     AddUser {
         /// Name of the user to be added to the ledger
         #[clap(value_parser)]
+        name: String,
+
+        /// Add the user even if another user already has the same name
+        #[clap(long, action)]
+        allow_duplicate: bool
+    },
+    /// Change the name of a user. Past transactions keep referring to them.
+    RenameUser {
+        /// Current name or id of the user
+        #[clap(value_parser)]
+        old: String,
+
+        /// New name for the user
+        #[clap(value_parser)]
+        new: String
+    },
+    /// Stop a user with a zero balance from taking part in new transactions, keeping their history
+    DeactivateUser {
+        /// Name or id of the user to deactivate
+        #[clap(value_parser)]
+        name: String
+    },
+    /// Allow a deactivated user to take part in new transactions again
+    ReactivateUser {
+        /// Name or id of the user to reactivate
+        #[clap(value_parser)]
+        name: String
+    },
+    /// Remove a user with a zero balance who is not part of any transaction or recurring expense
+    RemoveUser {
+        /// Name or id of the user to remove
+        #[clap(value_parser)]
         name: String
     },
     /// Add a new direct transfer
     AddDirect(AddDirect),
     /// Add a new expense
     AddExpense(AddExpense),
+    /// Add an itemized receipt, with each item shared between its own beneficiaries
+    AddReceipt(AddReceipt),
+    /// Show the transfers needed to settle all balances
+    Settle {
+        /// Record the suggested transfers on the ledger
+        #[clap(short='r', long, action)]
+        record: bool,
+
+        /// The time to record the transfers at. Example format: "2022-05-01 12:21". Default is now.
+        #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
+        time: Option<DateTime<Utc>>
+    },
+    /// Manage expenses which repeat on a schedule, such as rent
+    Recurring {
+        #[clap(subcommand)]
+        action: RecurringAction
+    },
+    /// Manage exchange rates to the base currency
+    Rates {
+        #[clap(subcommand)]
+        action: RatesAction
+    },
+    /// Change an existing transaction, keeping the previous version in its history
+    Edit(Edit),
+    /// Show the previous versions of an edited transaction
+    History {
+        /// Id of the transaction (as appears in output of 'list')
+        #[clap(parse(try_from_str = parse_hex_to_int), required=true)]
+        id: usize
+    },
     /// Undo an existing transaction
     Undo  {
         /// Id of the transaction to undo (as appears in output of 'list')
@@ -53,21 +161,138 @@ enum Subcommands {
     }
 }
 
+#[derive(Clone, Debug, ValueEnum)]
+enum PeriodArg {
+    Week,
+    Month,
+    Year
+}
+
+impl PeriodArg {
+    fn period(&self) -> Period {
+        match self {
+            PeriodArg::Week => Period::Week,
+            PeriodArg::Month => Period::Month,
+            PeriodArg::Year => Period::Year
+        }
+    }
+}
+
+fn print_report(ledger: &Ledger, period: Period) -> ActionResult {
+    let report = report::spending_report(ledger, period)?;
+    let name_width = ledger.get_users().iter()
+        .map(|user| ledger.display_name(&user.id).chars().count())
+        .max().unwrap_or(0).max("User".len());
+
+    println!("{:<10} {:<name_width$} {:>10} {:>10} {:>10}",
+        "Period".bold(), "User".bold(), "Paid".bold(), "Consumed".bold(), "Net".bold(), name_width = name_width);
+    for (start, per_user) in report {
+        let mut rows: Vec<(String, Spending)> = per_user.into_iter()
+            .map(|(user, spending)| (ledger.display_name(&user), spending))
+            .collect();
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, spending) in rows {
+            println!("{:<10} {:<name_width$} {:>10} {:>10} {:>10}", period.label(start), name,
+                spending.paid, spending.consumed, spending.net(), name_width = name_width);
+        }
+    }
+    return Ok(());
+}
+
+#[derive(Debug, Subcommand)]
+enum RecurringAction {
+    /// Add a new recurring expense
+    Add(Box<AddRecurring>),
+    /// List all recurring expenses
+    List,
+    /// Stop repeating an expense until it is resumed
+    Pause {
+        /// Id of the recurring expense (as appears in output of 'recurring list')
+        #[clap(value_parser)]
+        id: usize
+    },
+    /// Start repeating a paused expense again from today, skipping what was due while paused
+    Resume {
+        /// Id of the recurring expense (as appears in output of 'recurring list')
+        #[clap(value_parser)]
+        id: usize
+    },
+    /// Record all repetitions which are due
+    Run {
+        /// Record repetitions due up to and including this day. Example format: "2022-05-01". Default is today.
+        #[clap(long, value_parser)]
+        until: Option<NaiveDate>
+    }
+}
+
+#[derive(Args, Debug)]
+struct AddRecurring {
+    #[clap(flatten)]
+    expense: ExpenseArgs,
+
+    /// When the expense repeats: `monthly:DAY` with a day of the month, or `weekly:DAY`
+    /// with a day of the week. Example: `monthly:1` or `weekly:fri`
+    #[clap(short='s', long, value_parser)]
+    schedule: Schedule,
+
+    /// First day the expense can happen on. Example format: "2022-05-01". Default is today.
+    #[clap(long, value_parser)]
+    start: Option<NaiveDate>,
+
+    /// Last day the expense can happen on. Default is to repeat forever.
+    #[clap(long, value_parser)]
+    end: Option<NaiveDate>
+}
+
+#[derive(Debug, Subcommand)]
+enum RatesAction {
+    /// List all known exchange rates
+    List,
+    /// Import exchange rates from a file
+    Import {
+        /// A CSV file with lines of `date,currency,rate`, e.g. `2022-05-01,USD,0.95`,
+        /// or a JSON file with a list of objects with `date`, `currency` and `rate` fields.
+        /// Each rate is how much one unit of the currency is worth in the base currency.
+        #[clap(value_parser)]
+        file: PathBuf
+    }
+}
+
+fn read_rates_file(path: &Path) -> result::Result<Vec<RateEntry>, Box<dyn error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+        return Ok(serde_json::from_str(&contents)?);
+    }
+    return Ok(rates::parse_csv(&contents)?);
+}
+
+/// Prints the transfers which would settle all balances, and returns them.
+/// The transfers in a settlement plan, one per line, with the users' names.
+fn describe_settlement_plan(ledger: &Ledger, plan: &[Settlement]) -> Vec<String> {
+    if plan.is_empty() {
+        return vec![String::from("All settled up")];
+    }
+    return plan.iter()
+        .map(|settlement| format!("{} -> {}: {}", ledger.display_name(&settlement.from),
+            ledger.display_name(&settlement.to), settlement.amount))
+        .collect();
+}
+
 fn parse_hex_to_int(arg: &str) -> Result<usize, std::num::ParseIntError> {
     usize::from_str_radix(arg, 16)
 }
 
-fn print_balances(ledger: &Ledger) {
-    for (user, balance) in ledger.get_balances() {
-        let color = if balance < 0.0 {
+fn print_balances(ledger: &Ledger, balances: UserAmountMap) {
+    for (user, balance) in balances {
+        let color = if balance.is_negative() {
             colored::ColoredString::bright_red
-        } else if balance > 0.0 {
+        } else if balance.is_positive() {
             colored::ColoredString::green
         } else {
             colored::ColoredString::normal
         };
-        let fmt_balance = color(format!("{:.2}", balance).white());
-        println!("{}: {}", user, fmt_balance);
+        let fmt_balance = color(balance.to_string().white());
+        println!("{}: {}", ledger.display_name(&user), fmt_balance);
     }
 }
 
@@ -78,14 +303,15 @@ fn parse_time_minutes(arg: &str) -> Result<DateTime<Utc>, chrono::format::ParseE
 
 #[derive(Args, Debug)]
 struct AddDirect {
-    /// Name of user that paid
+    /// Name or id of user that paid
     #[clap(short='f', long, value_parser)]
     from: String,
 
-    /// Name of user that got paid
+    /// Name or id of user that got paid
     #[clap(short='t', long, value_parser)]
     to: String,
 
+    /// Amount transferred, with at most two decimal places
     #[clap(short='a', long, value_parser)]
     amount: Amount,
 
@@ -95,17 +321,163 @@ struct AddDirect {
 
     /// The time the transaction happened. Example format: "2022-05-01 12:21". Default is now.
     #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
-    time: Option<DateTime<Utc>>
+    time: Option<DateTime<Utc>>,
+
+    #[clap(flatten)]
+    currency: CurrencyArgs
 }
 
 impl AddDirect {
     fn add_direct(&self, ledger: &mut Ledger) -> TransactionResult<()> {
-        ledger.add_transfer(&self.from, &self.to, self.amount, &self.description, self.time)
+        let mut transaction = Transaction::new(
+            vec![(&self.from, self.amount)],
+            vec![(&self.to, Benefit::Sum(self.amount))],
+            &self.description, true, None, self.time);
+        self.currency.apply(&mut transaction);
+        ledger.add_transaction(transaction)
+    }
+}
+
+#[derive(Args, Debug)]
+struct CurrencyArgs {
+    /// Currency the amounts are in, as a three letter code. Default is the ledger's base currency.
+    #[clap(short='c', long, value_parser)]
+    currency: Option<Currency>,
+
+    /// How much one unit of `--currency` is worth in the ledger's base currency.
+    /// Default is the rate in effect on the day, from the ledger's imported rates.
+    #[clap(long, value_parser, requires = "currency")]
+    rate: Option<Rate>
+}
+
+impl CurrencyArgs {
+    fn apply(&self, transaction: &mut Transaction) {
+        transaction.currency = self.currency.clone();
+        transaction.rate = self.rate;
     }
 }
 
 #[derive(Args, Debug)]
 struct AddExpense {
+    #[clap(flatten)]
+    expense: ExpenseArgs,
+
+    /// The time the transaction happened. Example format: "2022-05-01 12:21". Default is now.
+    #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
+    time: Option<DateTime<Utc>>
+}
+
+impl AddExpense {
+    pub fn add_expense(&self, ledger: &mut Ledger) -> TransactionResult<()> {
+        let mut transaction = self.expense.transaction();
+        if let Some(time) = self.time {
+            transaction.datetime = time;
+        }
+        ledger.add_transaction(transaction)
+    }
+}
+
+#[derive(Args, Debug)]
+struct AddReceipt {
+    /// Name or id of the user who paid the bill
+    #[clap(short, long, value_parser)]
+    from: String,
+
+    /// A line of the receipt as `PRICE:USERS[:DESCRIPTION]`, with the users who
+    /// shared it separated by commas. Can be given several times.
+    /// Example: `--item 45:Ben:steak --item 30:Alex,Cara:wine`
+    #[clap(short, long = "item", parse(try_from_str = parse_item), required=true, multiple_occurrences=true)]
+    items: Vec<Item>,
+
+    #[clap(flatten)]
+    surcharges: SurchargeArgs,
+
+    /// Describe the purpose of the expense
+    #[clap(short, long, value_parser, default_value_t = String::from(""))]
+    description: String,
+
+    /// The time the transaction happened. Example format: "2022-05-01 12:21". Default is now.
+    #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
+    time: Option<DateTime<Utc>>,
+
+    /// What the money was spent on, e.g. `food` or `travel`
+    #[clap(long, value_parser)]
+    category: Option<String>,
+
+    /// Free-form label for the expense. Can be given several times.
+    #[clap(long = "tag", value_parser, multiple_occurrences = true)]
+    tags: Vec<String>,
+
+    #[clap(flatten)]
+    currency: CurrencyArgs
+}
+
+impl AddReceipt {
+    fn add_receipt(&self, ledger: &mut Ledger) -> TransactionResult<()> {
+        let mut transaction = Transaction::itemized(&self.from, self.items.clone(),
+            self.surcharges.surcharges(), &self.description, self.time);
+        transaction.category = self.category.clone();
+        transaction.tags = self.tags.clone();
+        self.currency.apply(&mut transaction);
+        ledger.add_transaction(transaction)
+    }
+}
+
+fn parse_item(arg: &str) -> Result<Item, String> {
+    let mut parts = arg.splitn(3, ':');
+    let price = parts.next().unwrap_or_default();
+    let users = parts.next().ok_or_else(|| format!("expected PRICE:USERS[:DESCRIPTION], got {}", arg))?;
+    let price: Amount = price.parse().map_err(|err: divider::amount::ParseAmountError| err.to_string())?;
+    let beneficiaries: Vec<String> = users.split(',')
+        .filter(|user| !user.is_empty())
+        .map(|user| user.to_owned())
+        .collect();
+    let description = parts.next().unwrap_or_default().to_owned();
+    return Ok(Item { description, price, beneficiaries });
+}
+
+/// Charges on top of an expense or the items of a receipt. Each is either
+/// a percentage of what they come to, written with a `%` suffix, or a fixed amount.
+#[derive(Args, Debug)]
+struct SurchargeArgs {
+    /// Tax, e.g. `8.5%`
+    #[clap(long, value_parser, allow_hyphen_values = true)]
+    tax: Option<SurchargeValue>,
+
+    /// Service charge, e.g. `12.5%`
+    #[clap(long, value_parser, allow_hyphen_values = true)]
+    service: Option<SurchargeValue>,
+
+    /// Tip, e.g. `15%` or `10`
+    #[clap(long, value_parser, allow_hyphen_values = true)]
+    tip: Option<SurchargeValue>,
+
+    /// Discount taken off, e.g. `10%` or `5`
+    #[clap(long, value_parser)]
+    discount: Option<SurchargeValue>
+}
+
+impl SurchargeArgs {
+    fn surcharges(&self) -> Vec<Surcharge> {
+        let charges = [
+            ("Tax", self.tax),
+            ("Service", self.service),
+            ("Tip", self.tip),
+            ("Discount", self.discount.map(|discount| -discount))
+        ];
+        return charges.into_iter()
+            .filter_map(|(name, value)| value.map(|value| Surcharge::new(name, value)))
+            .collect();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.surcharges().is_empty();
+    }
+}
+
+/// Everything describing an expense, apart from when it happened.
+#[derive(Args, Debug)]
+struct ExpenseArgs {
     /// Pairs of: (name, amount) contributed to this expense. space separated.
     /// Example: `Donald 5 Will 29`
     #[clap(short, long, value_parser, required=true, min_values=1, multiple_occurrences=false)]
@@ -113,11 +485,15 @@ struct AddExpense {
 
     /// Names of beneficiaries of the expense. Specifying
     /// amount benefitted is optional, and if omitted "even"
-    /// benefit will be assumed.
+    /// benefit will be assumed. A number of shares can be given
+    /// instead of an amount, by suffixing it with `x`, or a whole
+    /// percentage of the total, by suffixing it with `%`.
     ///
     /// Examples:
     /// `Ben George Mike` -> split evenly between all three.
     /// `Ben 14 George Mike` -> Ben spent 14 and the rest is split evenly between George and Mike.
+    /// `Ben 2x George Mike` -> Ben takes two shares, George and Mike one share each.
+    /// `Ben 60% George 40%` -> Ben takes 60% of the total and George 40%.
     #[clap(short, long, value_parser, required=true, min_values=1, multiple_occurrences=false)]
     to: Vec<String>,
 
@@ -125,20 +501,70 @@ struct AddExpense {
     #[clap(short, long, value_parser, default_value_t = String::from(""))]
     description: String,
 
-    /// The time the transaction happened. Example format: "2022-05-01 12:21". Default is now.
-    #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
-    time: Option<DateTime<Utc>>
+    /// Who gets the cents left over when the even split is not exact
+    #[clap(short='r', long, value_enum, default_value_t = RemainderArg::RoundRobin)]
+    remainder: RemainderArg,
+
+    /// Seed used with `--remainder random`. Default is based on the current time.
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// What the money was spent on, e.g. `food` or `travel`
+    #[clap(long, value_parser)]
+    category: Option<String>,
+
+    /// Free-form label for the expense. Can be given several times.
+    #[clap(long = "tag", value_parser, multiple_occurrences = true)]
+    tags: Vec<String>,
+
+    /// Added on top of the amounts paid, and shared in proportion to what each beneficiary had
+    #[clap(flatten)]
+    surcharges: SurchargeArgs,
+
+    #[clap(flatten)]
+    currency: CurrencyArgs
 }
 
-impl AddExpense {
-    pub fn add_expense(&self, ledger: &mut Ledger) -> TransactionResult<()> {
-        let contributions: AmountPerUser<&str> = AddExpense::parse_contributors(&self.from);
-        let benefits: BenefitPerUser<&str> = AddExpense::parse_beneficiaries(&self.to);
+#[derive(Clone, Debug, ValueEnum)]
+enum RemainderArg {
+    /// One cent each, in the order the beneficiaries are listed
+    RoundRobin,
+    /// The person who paid the most takes the leftover
+    PayerAbsorbs,
+    /// Beneficiaries closest to rounding up get the leftover
+    LargestRemainder,
+    /// Beneficiaries picked at random get the leftover
+    Random
+}
+
+impl ExpenseArgs {
+    /// The expense as a transaction happening now.
+    fn transaction(&self) -> Transaction {
+        let contributions: AmountPerUser<&str> = ExpenseArgs::parse_contributors(&self.from);
+        let benefits: BenefitPerUser<&str> = ExpenseArgs::parse_beneficiaries(&self.to);
+
+        let mut transaction = Transaction::new(contributions, benefits, &self.description, false, None, None);
+        transaction.remainder = self.remainder_policy();
+        transaction.category = self.category.clone();
+        transaction.tags = self.tags.clone();
+        transaction.add_surcharges(self.surcharges.surcharges());
+        self.currency.apply(&mut transaction);
+        return transaction;
+    }
 
-        ledger.add_expense(contributions, benefits, &self.description, self.time)
+    fn remainder_policy(&self) -> RemainderPolicy {
+        match self.remainder {
+            RemainderArg::RoundRobin => RemainderPolicy::RoundRobin,
+            RemainderArg::PayerAbsorbs => RemainderPolicy::PayerAbsorbs,
+            RemainderArg::LargestRemainder => RemainderPolicy::LargestRemainder,
+            RemainderArg::Random => {
+                let seed = self.seed.unwrap_or_else(|| Utc::now().timestamp_nanos() as u64);
+                RemainderPolicy::Random { seed }
+            }
+        }
     }
 
-    fn parse_contributors(arguments: &Vec<String>) -> AmountPerUser<&str> {
+    fn parse_contributors(arguments: &[String]) -> AmountPerUser<&str> {
         let mut contributions: AmountPerUser<&str> = AmountPerUser::new();
 
         for slice in arguments.chunks(2) {
@@ -152,23 +578,23 @@ impl AddExpense {
         return contributions;
     }
 
-    fn parse_beneficiaries(arguments: &Vec<String>) -> BenefitPerUser<&str> {
+    fn parse_beneficiaries(arguments: &[String]) -> BenefitPerUser<&str> {
         let mut beneficiaries: BenefitPerUser<&str> = BenefitPerUser::new();
         let mut prev_user: Option<&str> = None;
 
         for val in arguments {
-            match val.parse::<Amount>() {
-                Ok(amount) => {
-                    // this is the amount that the previous user benefitted
+            match ExpenseArgs::parse_benefit(val) {
+                Some(benefit) => {
+                    // this is how much the previous user benefitted
                     match prev_user {
-                        None => panic!("Expected a user before {}", amount),
+                        None => panic!("Expected a user before {}", val),
                         Some(user) => {
-                            beneficiaries.push((user, Benefit::Sum(amount)));
+                            beneficiaries.push((user, benefit));
                             prev_user = None;
                         }
                     }
                 },
-                Err(_) => {
+                None => {
                     // this is not a number so it must be a user
                     // if we have a prev_user, its benefit is Even
                     if let Some(user) = prev_user {
@@ -184,56 +610,447 @@ impl AddExpense {
 
         return beneficiaries;
     }
+
+    fn parse_benefit(arg: &str) -> Option<Benefit> {
+        if let Some(weight) = arg.strip_suffix('x').and_then(|w| w.parse::<u32>().ok()) {
+            return Some(Benefit::Shares(weight));
+        }
+        if let Some(pct) = arg.strip_suffix('%').and_then(|p| p.parse::<u32>().ok()) {
+            return Some(Benefit::Percent(pct));
+        }
+        return arg.parse::<Amount>().ok().map(Benefit::Sum);
+    }
+}
+
+fn today() -> NaiveDate {
+    return chrono::offset::Local::now().naive_local().date();
+}
+
+fn parse_day_start(arg: &str) -> Result<DateTime<Utc>, String> {
+    let date = NaiveDate::parse_from_str(arg, "%F").map_err(|err| err.to_string())?;
+    return chrono::offset::Local.from_local_datetime(&date.and_hms(0, 0, 0)).earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("{} has no local midnight", arg));
+}
+
+#[derive(Args, Debug)]
+struct ListFilter {
+    /// Only transactions involving this user, by name or id. Can be given several times.
+    #[clap(short='u', long = "user", value_parser, multiple_occurrences = true)]
+    users: Vec<String>,
+
+    /// Only transactions on or after this day. Example format: "2022-05-01"
+    #[clap(long, parse(try_from_str = parse_day_start))]
+    since: Option<DateTime<Utc>>,
+
+    /// Only transactions on or before this day. Example format: "2022-05-31"
+    #[clap(long, parse(try_from_str = parse_day_start))]
+    until: Option<DateTime<Utc>>,
+
+    /// Only transactions of at least this amount, in the base currency
+    #[clap(long, value_parser)]
+    min: Option<Amount>,
+
+    /// Only transactions of at most this amount, in the base currency
+    #[clap(long, value_parser)]
+    max: Option<Amount>,
+
+    /// Only transactions whose description matches this regular expression
+    #[clap(short='g', long, value_parser)]
+    grep: Option<Regex>,
+
+    /// Only transactions in this category
+    #[clap(long, value_parser)]
+    category: Option<String>
+}
+
+impl ListFilter {
+    fn query<'a>(&self, ledger: &'a Ledger) -> Query<'a> {
+        let mut query = ledger.query();
+        for user in &self.users {
+            query = query.involving(user);
+        }
+        if let Some(since) = self.since {
+            query = query.since(since);
+        }
+        if let Some(until) = self.until {
+            query = query.before(until + chrono::Duration::days(1));
+        }
+        if let Some(min) = self.min {
+            query = query.min_amount(min);
+        }
+        if let Some(max) = self.max {
+            query = query.max_amount(max);
+        }
+        if let Some(pattern) = &self.grep {
+            query = query.description_matches(pattern.clone());
+        }
+        if let Some(category) = &self.category {
+            query = query.category(category);
+        }
+        return query;
+    }
+}
+
+#[derive(Args, Debug)]
+struct Edit {
+    /// Id of the transaction to change (as appears in output of 'list')
+    #[clap(parse(try_from_str = parse_hex_to_int), required=true)]
+    id: usize,
+
+    /// Replace the contributions, given in the same way as for 'add-expense'
+    #[clap(short, long, value_parser, min_values=1, multiple_occurrences=false)]
+    from: Option<Vec<String>>,
+
+    /// Replace the beneficiaries, given in the same way as for 'add-expense'
+    #[clap(short, long, value_parser, min_values=1, multiple_occurrences=false)]
+    to: Option<Vec<String>>,
+
+    /// New description
+    #[clap(short, long, value_parser)]
+    description: Option<String>,
+
+    /// New time of the transaction. Example format: "2022-05-01 12:21"
+    #[clap(short='T', long, parse(try_from_str = parse_time_minutes))]
+    time: Option<DateTime<Utc>>,
+
+    /// Whether this is a direct transfer rather than an expense
+    #[clap(long, value_parser)]
+    direct: Option<bool>,
+
+    /// Replace the items of a receipt, given in the same way as for 'add-receipt'
+    #[clap(short, long = "item", parse(try_from_str = parse_item), multiple_occurrences=true)]
+    items: Vec<Item>,
+
+    /// Replace the surcharges. If any are given, the others are removed.
+    #[clap(flatten)]
+    surcharges: SurchargeArgs,
+
+    /// Remove all the surcharges
+    #[clap(long, value_parser, conflicts_with_all = &["tax", "service", "tip", "discount"])]
+    no_surcharges: bool
+}
+
+impl Edit {
+    fn edit(&self, ledger: &mut Ledger) -> TransactionResult<()> {
+        let patch = TransactionPatch {
+            contributions: self.from.as_ref().map(|from| {
+                ExpenseArgs::parse_contributors(from).into_iter()
+                    .map(|(user, amount)| (user.to_owned(), amount))
+                    .collect()
+            }),
+            benefits: self.to.as_ref().map(|to| {
+                ExpenseArgs::parse_beneficiaries(to).into_iter()
+                    .map(|(user, benefit)| (user.to_owned(), benefit))
+                    .collect()
+            }),
+            description: self.description.clone(),
+            datetime: self.time,
+            is_direct: self.direct,
+            items: if self.items.is_empty() { None } else { Some(self.items.clone()) },
+            surcharges: if self.no_surcharges {
+                Some(Vec::new())
+            } else if self.surcharges.is_empty() {
+                None
+            } else {
+                Some(self.surcharges.surcharges())
+            }
+        };
+        ledger.edit_transaction(self.id, patch)
+    }
 }
 
 type ActionResult = result::Result<(), Box<dyn error::Error>>;
 
 fn execute_action(action: Subcommands, store: &dyn LedgerStore) -> ActionResult {
     match action {
-        Subcommands::New{ names } => {
-            let ledger = Ledger::new(names);
-            store.save(&ledger)
+        Subcommands::New{ names, currency } => {
+            let mut ledger = Ledger::new(names);
+            if let Some(currency) = currency {
+                ledger.set_base_currency(currency)?;
+            }
+            let _lock = store.lock()?;
+            store.save(&mut ledger, 0)
         }
-        Subcommands::Balances => {
+        Subcommands::Balances{ per_currency, at } => {
             let ledger = store.read()?;
-            print_balances(&ledger);
+            if let Some(time) = at {
+                print_balances(&ledger, ledger.balances_at(time)?);
+                return Ok(());
+            }
+            if !per_currency {
+                print_balances(&ledger, ledger.get_balances());
+                return Ok(());
+            }
+            for (currency, balances) in ledger.get_balances_by_currency()? {
+                println!("{}", currency.to_string().bold());
+                print_balances(&ledger, balances);
+            }
             Ok(())
         },
-        Subcommands::List => {
+        Subcommands::List(filter) => {
             let ledger = store.read()?;
-            for t in ledger.get_transactions() {
-                println!("{}", t);
+            let matching: Vec<&Transaction> = filter.query(&ledger).iter()?.collect();
+            for t in &matching {
+                // undos are listed right below the transaction they undo
+                if let Some(original) = t.reverses {
+                    if matching.iter().any(|m| m.id == original) {
+                        continue;
+                    }
+                }
+                println!("{}", ledger.with_display_names(t));
+                // an undo outside the filters is only mentioned
+                match ledger.get_reversal(t.id) {
+                    Some(reversal) if matching.iter().any(|m| m.id == reversal.id) => {
+                        println!("  {} {}", "↳".dimmed(), ledger.with_display_names(reversal));
+                    },
+                    Some(_) => println!("  {} {}", "↳".dimmed(), "undone".dimmed()),
+                    None => ()
+                }
             };
             Ok(())
         }
-        Subcommands::AddUser{ name } => {
-            let mut ledger = store.read()?;
-            ledger.add_user(&name);
-            store.save(&ledger)
+        Subcommands::Users => {
+            let ledger = store.read()?;
+            let mut users = ledger.get_users();
+            users.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+            for user in users {
+                let status = if user.active { "" } else { " (inactive)" };
+                println!("{} {}{}", user.id, user.name, status);
+            }
+            Ok(())
+        }
+        Subcommands::Report{ period } => {
+            let ledger = store.read()?;
+            print_report(&ledger, period.period())
+        },
+        Subcommands::Categories{ per_user } => {
+            let ledger = store.read()?;
+            let category_name = |category: Option<String>| category.unwrap_or_else(|| String::from("(none)"));
+            if !per_user {
+                for (category, amount) in ledger.spending_by_category()? {
+                    println!("{}: {}", category_name(category), amount);
+                }
+                return Ok(());
+            }
+            for (user, by_category) in ledger.spending_by_user_and_category()? {
+                println!("{}", ledger.display_name(&user).bold());
+                for (category, amount) in by_category {
+                    println!("  {}: {}", category_name(category), amount);
+                }
+            }
+            Ok(())
+        },
+        Subcommands::AddUser{ name, allow_duplicate } => {
+            let mut id = String::new();
+            store.update(&mut |ledger| {
+                id = ledger.add_user(&name, allow_duplicate)?;
+                Ok(())
+            })?;
+            println!("Added {} with id {}", name, id);
+            Ok(())
+        },
+        Subcommands::RenameUser{ old, new } => {
+            store.update(&mut |ledger| {
+                ledger.rename_user(&old, &new)?;
+                Ok(())
+            })
+        },
+        Subcommands::DeactivateUser{ name } => {
+            store.update(&mut |ledger| {
+                ledger.deactivate_user(&name)?;
+                Ok(())
+            })
+        },
+        Subcommands::ReactivateUser{ name } => {
+            store.update(&mut |ledger| {
+                ledger.reactivate_user(&name)?;
+                Ok(())
+            })
+        },
+        Subcommands::RemoveUser{ name } => {
+            store.update(&mut |ledger| {
+                ledger.remove_user(&name)?;
+                Ok(())
+            })
         },
         Subcommands::AddDirect(add_direct) => {
-            let mut ledger = store.read()?;
-            add_direct.add_direct(&mut ledger)?;
-            store.save(&ledger)
+            store.update(&mut |ledger| {
+                add_direct.add_direct(ledger)?;
+                Ok(())
+            })
         },
         Subcommands::AddExpense(add_expense) => {
-            let mut ledger = store.read()?;
-            add_expense.add_expense(&mut ledger)?;
-            store.save(&ledger)
+            store.update(&mut |ledger| {
+                add_expense.add_expense(ledger)?;
+                Ok(())
+            })
+        },
+        Subcommands::AddReceipt(add_receipt) => {
+            store.update(&mut |ledger| {
+                add_receipt.add_receipt(ledger)?;
+                Ok(())
+            })
+        },
+        Subcommands::Settle{ record, time } => {
+            if !record {
+                let ledger = store.read()?;
+                for line in describe_settlement_plan(&ledger, &ledger.settlement_plan()) {
+                    println!("{}", line);
+                }
+                return Ok(());
+            }
+            let mut recorded = Vec::new();
+            store.update(&mut |ledger| {
+                let plan = ledger.settlement_plan();
+                recorded = describe_settlement_plan(ledger, &plan);
+                for settlement in &plan {
+                    ledger.record_settlement(settlement, "Settle up", time)?;
+                }
+                Ok(())
+            })?;
+            for line in recorded {
+                println!("{}", line);
+            }
+            Ok(())
+        },
+        Subcommands::Recurring{ action: RecurringAction::Add(add) } => {
+            let mut id = 0;
+            store.update(&mut |ledger| {
+                let start = add.start.unwrap_or_else(today);
+                id = ledger.add_recurring(add.expense.transaction(), add.schedule, start, add.end)?;
+                Ok(())
+            })?;
+            println!("Added recurring expense {}", id);
+            Ok(())
+        },
+        Subcommands::Recurring{ action: RecurringAction::List } => {
+            let ledger = store.read()?;
+            for recurring in ledger.get_recurring() {
+                println!("{}", recurring);
+            }
+            Ok(())
+        },
+        Subcommands::Recurring{ action: RecurringAction::Pause{ id } } => {
+            store.update(&mut |ledger| {
+                ledger.pause_recurring(id)?;
+                Ok(())
+            })
+        },
+        Subcommands::Recurring{ action: RecurringAction::Resume{ id } } => {
+            store.update(&mut |ledger| {
+                ledger.resume_recurring(id, today())?;
+                Ok(())
+            })
+        },
+        Subcommands::Recurring{ action: RecurringAction::Run{ until } } => {
+            let mut run = RecurringRun::default();
+            store.update(&mut |ledger| {
+                run = ledger.materialize_recurring(until.unwrap_or_else(today));
+                Ok(())
+            })?;
+            println!("Recorded {} transactions", run.created);
+            for (id, err) in &run.failed {
+                println!("{}: recurring expense {} was not run: {}", "Warning".yellow().bold(), id, err);
+            }
+            if !run.failed.is_empty() {
+                return Err(format!("{} recurring expenses could not be run", run.failed.len()).into());
+            }
+            Ok(())
+        },
+        Subcommands::Rates{ action: RatesAction::List } => {
+            let ledger = store.read()?;
+            for entry in ledger.get_rates().entries() {
+                println!("{}", entry);
+            }
+            Ok(())
+        },
+        Subcommands::Rates{ action: RatesAction::Import{ file } } => {
+            let mut imported = 0;
+            store.update(&mut |ledger| {
+                let entries = read_rates_file(&file)?;
+                imported = entries.len();
+                ledger.import_rates(entries)?;
+                Ok(())
+            })?;
+            println!("Imported {} rates", imported);
+            Ok(())
+        },
+        Subcommands::Edit(edit) => {
+            store.update(&mut |ledger| {
+                edit.edit(ledger)?;
+                Ok(())
+            })
+        },
+        Subcommands::History{ id } => {
+            let ledger = store.read()?;
+            for revision in ledger.get_revisions(id) {
+                let edited_at = revision.edited_at.with_timezone(&chrono::Local).format("%F %R");
+                println!("{} {}", "Replaced".bold(), edited_at);
+                println!("{}", ledger.with_display_names(&revision.transaction));
+            }
+            if let Some(current) = ledger.get_transactions().iter().find(|t| t.id == id) {
+                println!("{}", "Current".bold());
+                println!("{}", ledger.with_display_names(current));
+            }
+            Ok(())
         },
         Subcommands::Undo{ id } => {
-            let mut ledger = store.read()?;
-            ledger.reverse_by_id(id)?;
-            store.save(&ledger)
+            store.update(&mut |ledger| {
+                ledger.reverse_by_id(id)?;
+                Ok(())
+            })
         }
     }
 }
 
+type StoreResult = result::Result<Box<dyn LedgerStore>, Box<dyn error::Error>>;
+
+const SQLITE_EXTENSIONS: [&str; 3] = ["sqlite", "sqlite3", "db"];
+
+/// The store for the ledger at `path`: an SQLite database for `sqlite:` paths and
+/// `.sqlite`, `.sqlite3` or `.db` files, a file per transaction for directories (or paths
+/// ending in a separator), a log of events for `.jsonl` files, otherwise a JSON snapshot.
+fn open_store(path: &Path, lock_timeout: Duration) -> StoreResult {
+    if let Some(database) = sqlite_path(path) {
+        return open_sqlite_store(&database, lock_timeout);
+    }
+    if path.is_dir() || path.to_string_lossy().ends_with(std::path::is_separator) {
+        return Ok(Box::new(DirectoryStore::new(path).with_lock_timeout(lock_timeout)));
+    }
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("jsonl")) {
+        return Ok(Box::new(EventLogStore::new(path).with_lock_timeout(lock_timeout)));
+    }
+    return Ok(Box::new(JsonStore::new(path).with_lock_timeout(lock_timeout)));
+}
+
+/// The database file, if `path` refers to an SQLite database.
+fn sqlite_path(path: &Path) -> Option<PathBuf> {
+    let path_str = path.to_string_lossy();
+    if let Some(database) = path_str.strip_prefix("sqlite://").or_else(|| path_str.strip_prefix("sqlite:")) {
+        return Some(PathBuf::from(database));
+    }
+    let is_database = path.extension()
+        .is_some_and(|ext| SQLITE_EXTENSIONS.iter().any(|sqlite_ext| ext.eq_ignore_ascii_case(sqlite_ext)));
+    return if is_database { Some(path.to_owned()) } else { None };
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite_store(path: &Path, lock_timeout: Duration) -> StoreResult {
+    return Ok(Box::new(SqliteStore::new(path).with_lock_timeout(lock_timeout)));
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite_store(path: &Path, _lock_timeout: Duration) -> StoreResult {
+    return Err(format!("{} is an SQLite database, but this build has no SQLite support \
+        (install with --features sqlite)", path.display()).into());
+}
+
 fn main() -> ExitCode {
     let args = Cli::parse();
 
-    let store = JsonStore::new(&args.path);
-    let action_result: ActionResult = execute_action(args.action, &store);
+    let action_result: ActionResult = open_store(&args.path, Duration::from_secs(args.wait))
+        .and_then(|store| execute_action(args.action, store.as_ref()));
 
     match action_result {
         Ok(()) => return ExitCode::SUCCESS,
@@ -246,21 +1063,23 @@ fn main() -> ExitCode {
 
 #[cfg(test)]
 mod parser_tests {
+    use divider::Amount;
     use divider::transaction::Benefit;
     use rstest::rstest;
-    use crate::AddExpense;
-    use crate::parse_hex_to_int;
+    use crate::ExpenseArgs;
+    use crate::{parse_hex_to_int, parse_item, sqlite_path};
+    use std::path::{Path, PathBuf};
 
     #[rstest]
     fn parse_contributions_correct() {
         let cmdline = "Bilbo 12 Legolas 20";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let parsed = AddExpense::parse_contributors(&arguments);
+        let parsed = ExpenseArgs::parse_contributors(&arguments);
 
         assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed[0], ("Bilbo", 12.0));
-        assert_eq!(parsed[1], ("Legolas", 20.0));
+        assert_eq!(parsed[0], ("Bilbo", Amount::from_major(12)));
+        assert_eq!(parsed[1], ("Legolas", Amount::from_major(20)));
     }
 
     #[rstest]
@@ -269,7 +1088,7 @@ mod parser_tests {
         let cmdline = "Bilbo 12 Legolas";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let _ = AddExpense::parse_contributors(&arguments);
+        let _ = ExpenseArgs::parse_contributors(&arguments);
     }
 
     #[rstest]
@@ -278,7 +1097,7 @@ mod parser_tests {
         let cmdline = "Bilbo 12 Legolas abcdef";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let _ = AddExpense::parse_contributors(&arguments);
+        let _ = ExpenseArgs::parse_contributors(&arguments);
     }
 
     #[rstest]
@@ -286,7 +1105,7 @@ mod parser_tests {
         let cmdline = "Aragorn";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let beneficiaries = AddExpense::parse_beneficiaries(&arguments);
+        let beneficiaries = ExpenseArgs::parse_beneficiaries(&arguments);
 
         assert_eq!(beneficiaries.len(), 1);
         assert_eq!(beneficiaries[0], ("Aragorn", Benefit::Even));
@@ -297,7 +1116,7 @@ mod parser_tests {
         let cmdline = "Bilbo Legolas";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let beneficiaries = AddExpense::parse_beneficiaries(&arguments);
+        let beneficiaries = ExpenseArgs::parse_beneficiaries(&arguments);
 
         assert_eq!(beneficiaries.len(), 2);
         assert_eq!(beneficiaries[0], ("Bilbo", Benefit::Even));
@@ -309,11 +1128,36 @@ mod parser_tests {
         let cmdline = "Bilbo Legolas 24";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let beneficiaries = AddExpense::parse_beneficiaries(&arguments);
+        let beneficiaries = ExpenseArgs::parse_beneficiaries(&arguments);
 
         assert_eq!(beneficiaries.len(), 2);
         assert_eq!(beneficiaries[0], ("Bilbo", Benefit::Even));
-        assert_eq!(beneficiaries[1], ("Legolas", Benefit::Sum(24.0)));
+        assert_eq!(beneficiaries[1], ("Legolas", Benefit::Sum(Amount::from_major(24))));
+    }
+
+    #[rstest]
+    fn parse_beneficiaries_shares() {
+        let cmdline = "Bilbo 2x Legolas Gimli 3";
+        let arguments = cmdline.split(' ')
+            .map(|s| s.to_string()).collect::<Vec<String>>();
+        let beneficiaries = ExpenseArgs::parse_beneficiaries(&arguments);
+
+        assert_eq!(beneficiaries.len(), 3);
+        assert_eq!(beneficiaries[0], ("Bilbo", Benefit::Shares(2)));
+        assert_eq!(beneficiaries[1], ("Legolas", Benefit::Even));
+        assert_eq!(beneficiaries[2], ("Gimli", Benefit::Sum(Amount::from_major(3))));
+    }
+
+    #[rstest]
+    fn parse_beneficiaries_percent() {
+        let cmdline = "Ben 60% George 40%";
+        let arguments = cmdline.split(' ')
+            .map(|s| s.to_string()).collect::<Vec<String>>();
+        let beneficiaries = ExpenseArgs::parse_beneficiaries(&arguments);
+
+        assert_eq!(beneficiaries.len(), 2);
+        assert_eq!(beneficiaries[0], ("Ben", Benefit::Percent(60)));
+        assert_eq!(beneficiaries[1], ("George", Benefit::Percent(40)));
     }
 
     #[rstest]
@@ -322,7 +1166,7 @@ mod parser_tests {
         let cmdline = "Bilbo 24 30 Legolas";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let _ = AddExpense::parse_beneficiaries(&arguments);
+        let _ = ExpenseArgs::parse_beneficiaries(&arguments);
     }
 
     #[rstest]
@@ -331,7 +1175,7 @@ mod parser_tests {
         let cmdline = "31 Bilbo Legolas";
         let arguments = cmdline.split(' ')
             .map(|s| s.to_string()).collect::<Vec<String>>();
-        let _ = AddExpense::parse_beneficiaries(&arguments);
+        let _ = ExpenseArgs::parse_beneficiaries(&arguments);
     }
 
     #[rstest]
@@ -341,4 +1185,27 @@ mod parser_tests {
         assert_eq!(parse_hex_to_int("0ad8").unwrap(), 10 * 256 + 13 * 16 + 8);
         assert!(   parse_hex_to_int("00ga").is_err());
     }
+
+    #[rstest]
+    fn parse_items() {
+        let item = parse_item("12.50:Bilbo,Frodo:second breakfast").unwrap();
+        assert_eq!(item.price, Amount::from_minor(1250));
+        assert_eq!(item.beneficiaries, vec!["Bilbo", "Frodo"]);
+        assert_eq!(item.description, "second breakfast");
+
+        assert_eq!(parse_item("3:Gimli").unwrap().description, "");
+        assert!(parse_item("3").is_err());
+        assert!(parse_item("three:Gimli").is_err());
+    }
+
+    #[rstest]
+    #[case("ledger.sqlite", Some("ledger.sqlite"))]
+    #[case("ledger.DB", Some("ledger.DB"))]
+    #[case("sqlite:ledger", Some("ledger"))]
+    #[case("sqlite:///srv/ledger.bin", Some("/srv/ledger.bin"))]
+    #[case("ledger.json", None)]
+    #[case("sqlite/", None)]
+    fn sqlite_paths(#[case] path: &str, #[case] database: Option<&str>) {
+        assert_eq!(sqlite_path(Path::new(path)), database.map(PathBuf::from));
+    }
 }
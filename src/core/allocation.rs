@@ -0,0 +1,149 @@
+use serde::{Serialize, Deserialize};
+
+use crate::core::amount::Amount;
+
+/// Decides who receives the minor units (e.g. cents) left over
+/// when an amount cannot be split exactly between beneficiaries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RemainderPolicy {
+    /// Leftover units are handed out one each to the shares
+    /// in the order they were listed.
+    #[default]
+    RoundRobin,
+    /// The largest contributor takes the whole leftover.
+    PayerAbsorbs,
+    /// Leftover units go to the shares with the largest fractional part,
+    /// ties broken by the order they were listed.
+    LargestRemainder,
+    /// Leftover units go to shares picked at random. The seed is stored
+    /// so that the same allocation is reproduced on every replay.
+    Random { seed: u64 }
+}
+
+/// Splits `total` into shares proportional to `weights`.
+///
+/// Returns one share per weight, and the part of `total` that was not
+/// allocated to any share. The leftover is only non-zero for
+/// [`RemainderPolicy::PayerAbsorbs`], and otherwise the shares always
+/// add up to exactly `total`.
+pub fn split(total: Amount, weights: &[u64], policy: RemainderPolicy) -> (Vec<Amount>, Amount) {
+    let weight_sum: u64 = weights.iter().sum();
+    if weight_sum == 0 {
+        return (vec![Amount::ZERO; weights.len()], total);
+    }
+
+    let total_minor = total.minor() as i128;
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut fractions = Vec::with_capacity(weights.len());
+    for &weight in weights {
+        let numerator = total_minor * weight as i128;
+        shares.push((numerator / weight_sum as i128) as i64);
+        fractions.push(numerator % weight_sum as i128);
+    }
+
+    let allocated: i64 = shares.iter().sum();
+    let leftover = total.minor() - allocated;
+    let step = leftover.signum();
+    let mut order: Vec<usize> = (0..weights.len()).filter(|&i| weights[i] > 0).collect();
+
+    match policy {
+        RemainderPolicy::PayerAbsorbs => {
+            let shares = shares.into_iter().map(Amount::from_minor).collect();
+            return (shares, Amount::from_minor(leftover));
+        },
+        RemainderPolicy::RoundRobin => {},
+        RemainderPolicy::LargestRemainder => {
+            // stable sort keeps the listed order between equal fractions
+            order.sort_by(|&a, &b| fractions[b].abs().cmp(&fractions[a].abs()));
+        },
+        RemainderPolicy::Random { seed } => {
+            shuffle(&mut order, seed);
+        }
+    }
+
+    for &idx in order.iter().cycle().take(leftover.unsigned_abs() as usize) {
+        shares[idx] += step;
+    }
+    let shares = shares.into_iter().map(Amount::from_minor).collect();
+    return (shares, Amount::ZERO);
+}
+
+/// Fisher-Yates shuffle driven by SplitMix64, so that
+/// the outcome only depends on the seed.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Amount;
+    use crate::core::allocation::{split, RemainderPolicy};
+    use rstest::rstest;
+
+    fn minor(shares: &[Amount]) -> Vec<i64> {
+        shares.iter().map(|a| a.minor()).collect()
+    }
+
+    #[rstest]
+    fn round_robin() {
+        let (shares, leftover) = split(Amount::from_major(10), &[1, 1, 1], RemainderPolicy::RoundRobin);
+        assert_eq!(minor(&shares), vec![334, 333, 333]);
+        assert_eq!(leftover, Amount::ZERO);
+
+        let (shares, _) = split(Amount::from_minor(1001), &[1, 1, 1], RemainderPolicy::RoundRobin);
+        assert_eq!(minor(&shares), vec![334, 334, 333]);
+    }
+
+    #[rstest]
+    fn payer_absorbs() {
+        let (shares, leftover) = split(Amount::from_major(10), &[1, 1, 1], RemainderPolicy::PayerAbsorbs);
+        assert_eq!(minor(&shares), vec![333, 333, 333]);
+        assert_eq!(leftover, Amount::from_minor(1));
+    }
+
+    #[rstest]
+    fn largest_remainder() {
+        // quotas are 1.428..., 2.857..., 5.714...
+        let (shares, leftover) = split(Amount::from_minor(10), &[1, 2, 4], RemainderPolicy::LargestRemainder);
+        assert_eq!(minor(&shares), vec![1, 3, 6]);
+        assert_eq!(leftover, Amount::ZERO);
+    }
+
+    #[rstest]
+    fn random_is_reproducible() {
+        let total = Amount::from_minor(1003);
+        let weights = [1; 7];
+        let policy = RemainderPolicy::Random { seed: 42 };
+
+        let (first, _) = split(total, &weights, policy);
+        let (second, _) = split(total, &weights, policy);
+
+        assert_eq!(first, second);
+        assert_eq!(first.iter().sum::<Amount>(), total);
+    }
+
+    #[rstest]
+    fn negative_total() {
+        let (shares, _) = split(Amount::from_major(-10), &[1, 1, 1], RemainderPolicy::RoundRobin);
+        assert_eq!(minor(&shares), vec![-334, -333, -333]);
+    }
+
+    #[rstest]
+    fn no_weights() {
+        let (shares, leftover) = split(Amount::from_major(5), &[], RemainderPolicy::RoundRobin);
+        assert!(shares.is_empty());
+        assert_eq!(leftover, Amount::from_major(5));
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign, Neg, Mul};
+use std::str::FromStr;
+
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+
+/// An exact amount of money, stored as a whole number of minor units (e.g. cents).
+///
+/// Amounts are serialized as decimal strings such as `"12.50"`. For compatibility
+/// with older ledgers, plain JSON numbers are also accepted when deserializing
+/// and are rounded to the nearest minor unit.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    /// Largest amount accepted when parsing, either way from zero. It leaves
+    /// room for thousands of such amounts to be added up without overflowing.
+    pub const MAX: Amount = Amount(1_000_000_000_000_000);
+
+    /// Number of decimal places used to represent the minor unit.
+    pub const DECIMALS: u32 = 2;
+    const MINOR_PER_MAJOR: i64 = 10_i64.pow(Self::DECIMALS);
+
+    pub fn from_minor(minor: i64) -> Amount {
+        Amount(minor)
+    }
+
+    pub fn from_major(major: i64) -> Amount {
+        Amount(major * Self::MINOR_PER_MAJOR)
+    }
+
+    /// Like `from_minor`, but `None` if the amount is larger than [`Amount::MAX`].
+    pub fn checked_from_minor(minor: i64) -> Option<Amount> {
+        if minor.unsigned_abs() > Self::MAX.0.unsigned_abs() {
+            return None;
+        }
+        return Some(Amount(minor));
+    }
+
+    /// Like `from_major`, but `None` if the amount is larger than [`Amount::MAX`].
+    pub fn checked_from_major(major: i64) -> Option<Amount> {
+        major.checked_mul(Self::MINOR_PER_MAJOR).and_then(Amount::checked_from_minor)
+    }
+
+    /// The sum of two amounts, or `None` if it is too large to represent.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// Converts a floating point value, rounding to the nearest minor unit.
+    /// Only intended for reading legacy data.
+    pub fn from_f64_lossy(value: f64) -> Amount {
+        Amount((value * Self::MINOR_PER_MAJOR as f64).round() as i64)
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn abs(&self) -> Amount {
+        Amount(self.0.abs())
+    }
+
+    /// Divides the amount into `parts` equal shares, rounding towards zero.
+    /// Returns the share and the remainder left over, such that
+    /// `share * parts + remainder == self`.
+    pub fn div_rem(&self, parts: usize) -> (Amount, Amount) {
+        let parts = parts as i64;
+        return (Amount(self.0 / parts), Amount(self.0 % parts));
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let per_major = Self::MINOR_PER_MAJOR as u64;
+        let s = format!("{}{}.{:0width$}", sign, abs / per_major, abs % per_major,
+            width = Self::DECIMALS as usize);
+        f.pad(&s)
+    }
+}
+
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Amount({})", self)
+    }
+}
+
+/// Error parsing a decimal number, such as an amount or an exchange rate.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseAmountError {
+    /// The string is not a decimal number
+    Invalid(String),
+    /// The string has more decimal places than can be represented
+    TooPrecise(String),
+    /// The number does not fit in the range of representable amounts
+    Overflow(String)
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid(s) => write!(f, "not a valid amount: {}", s),
+            ParseAmountError::TooPrecise(s) => write!(f, "too many decimal places: {}", s),
+            ParseAmountError::Overflow(s) => write!(f, "amount out of range: {}", s)
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let minor = parse_fixed_point(s, Self::DECIMALS)?;
+        return Amount::checked_from_minor(minor).ok_or_else(|| ParseAmountError::Overflow(s.to_owned()));
+    }
+}
+
+/// Parses a decimal number such as `-12.5` into an integer
+/// number of units of `10^-decimals`.
+pub(crate) fn parse_fixed_point(s: &str, decimals: u32) -> Result<i64, ParseAmountError> {
+    let invalid = || ParseAmountError::Invalid(s.to_owned());
+    let overflow = || ParseAmountError::Overflow(s.to_owned());
+
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s))
+    };
+    let (whole, frac) = match unsigned.split_once('.') {
+        Some((whole, frac)) => (whole, frac),
+        None => (unsigned, "")
+    };
+
+    let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && frac.is_empty()) || !all_digits(whole) || !all_digits(frac) {
+        return Err(invalid());
+    }
+    if frac.len() > decimals as usize {
+        return Err(ParseAmountError::TooPrecise(s.to_owned()));
+    }
+
+    let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| overflow())? };
+    let padded_frac = format!("{:0<width$}", frac, width = decimals as usize);
+    let frac: i64 = if padded_frac.is_empty() { 0 } else { padded_frac.parse().map_err(|_| invalid())? };
+
+    let units = whole.checked_mul(10_i64.pow(decimals))
+        .and_then(|m| m.checked_add(frac))
+        .ok_or_else(overflow)?;
+    return Ok(if negative { -units } else { units });
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> de::Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount as a string or a number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        v.parse().map_err(de::Error::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+        Amount::checked_from_major(v).ok_or_else(|| E::custom(format!("amount out of range: {}", v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+        i64::try_from(v).ok().and_then(Amount::checked_from_major)
+            .ok_or_else(|| E::custom(format!("amount out of range: {}", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+        Amount::checked_from_minor(Amount::from_f64_lossy(v).0)
+            .ok_or_else(|| E::custom(format!("amount out of range: {}", v)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Amount, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+    fn add(self, rhs: Amount) -> Amount {
+        Amount(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+    fn sub(self, rhs: Amount) -> Amount {
+        Amount(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+impl Mul<i64> for Amount {
+    type Output = Amount;
+    fn mul(self, rhs: i64) -> Amount {
+        Amount(self.0 * rhs)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, |acc, x| acc + x)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, |acc, x| acc + *x)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::amount::{Amount, ParseAmountError};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("12", 1200)]
+    #[case("12.5", 1250)]
+    #[case("12.05", 1205)]
+    #[case("-0.07", -7)]
+    #[case("+3", 300)]
+    #[case(".5", 50)]
+    #[case("7.", 700)]
+    fn parse_valid(#[case] input: &str, #[case] minor: i64) {
+        assert_eq!(input.parse::<Amount>().unwrap(), Amount::from_minor(minor));
+    }
+
+    #[rstest]
+    fn parse_invalid() {
+        assert!(matches!("abc".parse::<Amount>(), Err(ParseAmountError::Invalid(..))));
+        assert!(matches!(".".parse::<Amount>(), Err(ParseAmountError::Invalid(..))));
+        assert!(matches!("1.2.3".parse::<Amount>(), Err(ParseAmountError::Invalid(..))));
+        assert!(matches!("1.234".parse::<Amount>(), Err(ParseAmountError::TooPrecise(..))));
+        assert!(matches!("99999999999999999999".parse::<Amount>(), Err(ParseAmountError::Overflow(..))));
+        assert!(matches!("10000000000000.01".parse::<Amount>(), Err(ParseAmountError::Overflow(..))));
+        assert_eq!("-10000000000000".parse::<Amount>(), Ok(-Amount::MAX));
+    }
+
+    #[rstest]
+    fn display() {
+        assert_eq!(Amount::from_minor(1205).to_string(), "12.05");
+        assert_eq!(Amount::from_minor(-7).to_string(), "-0.07");
+        assert_eq!(Amount::ZERO.to_string(), "0.00");
+        assert_eq!(format!("{:>7}", Amount::from_major(3)), "   3.00");
+    }
+
+    #[rstest]
+    fn div_rem() {
+        let (share, rem) = Amount::from_major(10).div_rem(3);
+        assert_eq!(share, Amount::from_minor(333));
+        assert_eq!(rem, Amount::from_minor(1));
+        assert_eq!(share * 3 + rem, Amount::from_major(10));
+    }
+
+    #[rstest]
+    fn serde_roundtrip() {
+        let amount = Amount::from_minor(3333);
+        let value = serde_json::to_value(amount).unwrap();
+        assert_eq!(value, serde_json::json!("33.33"));
+        assert_eq!(serde_json::from_value::<Amount>(value).unwrap(), amount);
+    }
+
+    #[rstest]
+    fn deserialize_legacy_numbers() {
+        assert_eq!(serde_json::from_str::<Amount>("33.333332").unwrap(), Amount::from_minor(3333));
+        assert_eq!(serde_json::from_str::<Amount>("-5.0").unwrap(), Amount::from_major(-5));
+        assert_eq!(serde_json::from_str::<Amount>("44").unwrap(), Amount::from_major(44));
+    }
+
+    #[rstest]
+    #[case("92233720368547759")]
+    #[case("10000000000001")]
+    #[case("1e20")]
+    #[case("-92233720368547759")]
+    #[case("18446744073709551615")]
+    fn deserialize_out_of_range_numbers(#[case] json: &str) {
+        assert!(serde_json::from_str::<Amount>(json).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::core::amount::{self, Amount, ParseAmountError};
+use crate::core::error::TransactionError;
+use crate::core::transaction::TransactionResult;
+
+/// A three letter ISO 4217 currency code, such as `EUR`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency(String);
+
+impl Currency {
+    /// Code reserved by ISO 4217 for "no currency". Used as the
+    /// base currency of ledgers which were not given one.
+    pub const NONE: &'static str = "XXX";
+
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Currency {
+        Currency(Self::NONE.to_owned())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({})", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a three letter currency code: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 3 || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(ParseCurrencyError(s.to_owned()));
+        }
+        return Ok(Currency(s.to_ascii_uppercase()));
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Currency, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+
+/// An exchange rate: how many units of one currency buy one unit of another.
+/// Stored exactly, with up to six decimal places.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(i64);
+
+impl Rate {
+    pub const DECIMALS: u32 = 6;
+    const UNITS_PER_ONE: i64 = 10_i64.pow(Self::DECIMALS);
+
+    pub const ONE: Rate = Rate(Self::UNITS_PER_ONE);
+
+    /// Converts an amount at this rate, rounding half away from zero
+    /// to the nearest minor unit. Fails if the result is too large to represent.
+    pub fn convert(&self, amount: Amount) -> TransactionResult<Amount> {
+        let scaled = amount.minor() as i128 * self.0 as i128;
+        let units = Self::UNITS_PER_ONE as i128;
+        let rounded = (scaled.abs() + units / 2) / units * scaled.signum();
+        return i64::try_from(rounded).map(Amount::from_minor).map_err(|_| TransactionError::AmountOverflow);
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / Self::UNITS_PER_ONE;
+        let frac = format!("{:0width$}", self.0 % Self::UNITS_PER_ONE, width = Self::DECIMALS as usize);
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            f.pad(&whole.to_string())
+        } else {
+            f.pad(&format!("{}.{}", whole, frac))
+        }
+    }
+}
+
+impl fmt::Debug for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate({})", self)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let units = amount::parse_fixed_point(s, Self::DECIMALS)?;
+        if units <= 0 {
+            return Err(ParseAmountError::Invalid(s.to_owned()));
+        }
+        return Ok(Rate(units));
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Rate, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Amount;
+    use crate::core::currency::{Currency, Rate};
+    use rstest::rstest;
+
+    #[rstest]
+    fn parse_currency() {
+        assert_eq!("eur".parse::<Currency>().unwrap().code(), "EUR");
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+        assert_eq!(Currency::default().code(), "XXX");
+    }
+
+    #[rstest]
+    fn parse_rate() {
+        assert_eq!("1.0845".parse::<Rate>().unwrap().to_string(), "1.0845");
+        assert_eq!("2".parse::<Rate>().unwrap().to_string(), "2");
+        assert_eq!("1".parse::<Rate>().unwrap(), Rate::ONE);
+        assert!("0".parse::<Rate>().is_err());
+        assert!("-1.2".parse::<Rate>().is_err());
+        assert!("1.0000001".parse::<Rate>().is_err());
+    }
+
+    #[rstest]
+    fn convert() {
+        let rate: Rate = "0.855".parse().unwrap();
+        assert_eq!(rate.convert(Amount::from_major(10)).unwrap(), Amount::from_minor(855));
+        assert_eq!(rate.convert(Amount::from_minor(1)).unwrap(), Amount::from_minor(1));
+        assert_eq!(rate.convert(Amount::from_minor(-1)).unwrap(), Amount::from_minor(-1));
+        assert_eq!(Rate::ONE.convert(Amount::from_minor(1234)).unwrap(), Amount::from_minor(1234));
+        assert!("1000000".parse::<Rate>().unwrap().convert(Amount::MAX).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::error;
 
 use crate::core::{Amount, UserName};
+use crate::core::currency::Currency;
 
 #[derive(Debug)]
 pub enum TransactionError {
@@ -16,12 +17,50 @@ pub enum TransactionError {
         specified: Amount,
         spent: Amount
     },
+    /// Occurs when the percentages given as a transaction's
+    /// benefits add up to more than 100%
+    ExcessPercentage {
+        specified: u64
+    },
+    /// Occurs when a transaction's benefits are all percentages,
+    /// but they add up to less than 100%
+    InsufficientPercentage {
+        specified: u64
+    },
+    /// Occurs when an item on a receipt has no beneficiaries
+    UnsharedItem(String),
     /// Occurs when attempting to register a transaction
     /// involving a user not registered on a ledger.
     UnknownUser(UserName),
+    /// Occurs when referring by name to a user whose name is shared with other users
+    AmbiguousUser(UserName),
+    /// Occurs when adding a user with the same name as an existing one
+    DuplicateUser(UserName),
+    /// Occurs when registering a new transaction involving a deactivated user
+    InactiveUser(UserName),
+    /// Occurs when removing a user whose balance is not zero
+    UserHasBalance(UserName, Amount),
+    /// Occurs when removing a user who is part of recorded transactions
+    UserHasTransactions(UserName),
+    /// Occurs when removing a user who is part of recurring transactions
+    UserHasRecurring(UserName),
     /// Occurs when attempting to reference a transaction
     /// by an id which does not exist on the ledger
-    UnknownTransactionId(usize)
+    UnknownTransactionId(usize),
+    /// Occurs when referring to a recurring transaction which does not exist
+    UnknownRecurringId(usize),
+    /// Occurs when undoing or editing a transaction which has already been undone
+    AlreadyReversed(usize),
+    /// Occurs when undoing or editing a transaction which is itself an undo
+    IsReversal(usize),
+    /// Occurs when a transaction is in a currency other than the
+    /// ledger's base currency, and no exchange rate is known for it
+    MissingExchangeRate(Currency),
+    /// Occurs when changing the base currency after transactions,
+    /// recurring transactions or exchange rates were recorded in it
+    BaseCurrencyInUse(Currency),
+    /// Occurs when amounts add up to more than can be represented
+    AmountOverflow
 }
 
 impl std::fmt::Display for TransactionError {
@@ -33,11 +72,56 @@ impl std::fmt::Display for TransactionError {
             TransactionError::ExcessBenefits { specified, spent } => {
                 write!(f, "too many benefits specified: {} out of {} spent", specified, spent)
             },
+            TransactionError::ExcessPercentage { specified } => {
+                write!(f, "percentages add up to more than 100%: {}%", specified)
+            },
+            TransactionError::InsufficientPercentage { specified } => {
+                write!(f, "percentages add up to less than 100%: {}%", specified)
+            },
+            TransactionError::UnsharedItem(description) => {
+                write!(f, "nobody is sharing the item '{}'", description)
+            },
             TransactionError::UnknownUser(username) => {
                 write!(f, "no such user: {}", username)
             },
+            TransactionError::AmbiguousUser(username) => {
+                write!(f, "several users are called {}, use their id instead", username)
+            },
+            TransactionError::DuplicateUser(username) => {
+                write!(f, "user already exists: {}", username)
+            },
+            TransactionError::InactiveUser(username) => {
+                write!(f, "user is deactivated: {}", username)
+            },
+            TransactionError::UserHasBalance(username, balance) => {
+                write!(f, "user {} still has a balance of {}", username, balance)
+            },
+            TransactionError::UserHasTransactions(username) => {
+                write!(f, "user {} is part of recorded transactions", username)
+            },
+            TransactionError::UserHasRecurring(username) => {
+                write!(f, "user {} is part of recurring transactions", username)
+            },
             TransactionError::UnknownTransactionId(id) => {
                 write!(f, "no such transaction id: {}", id)
+            },
+            TransactionError::UnknownRecurringId(id) => {
+                write!(f, "no such recurring transaction: {}", id)
+            },
+            TransactionError::AlreadyReversed(id) => {
+                write!(f, "transaction {:04x} has already been undone", id)
+            },
+            TransactionError::IsReversal(id) => {
+                write!(f, "transaction {:04x} undoes another transaction and can't be changed", id)
+            },
+            TransactionError::MissingExchangeRate(currency) => {
+                write!(f, "no exchange rate to convert {} to the base currency", currency)
+            },
+            TransactionError::BaseCurrencyInUse(currency) => {
+                write!(f, "the base currency {} can't be changed once transactions or exchange rates are recorded", currency)
+            },
+            TransactionError::AmountOverflow => {
+                write!(f, "amounts add up to more than can be recorded")
             }
         }
     }
@@ -0,0 +1,81 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+
+use crate::core::currency::Currency;
+use crate::core::rates::RateEntry;
+use crate::core::recurring::Recurring;
+use crate::core::transaction::Transaction;
+use crate::core::user::{User, UserId, UserName};
+
+/// A change made to a ledger. The ledger is the result of replaying all of its
+/// events in order, so they hold everything needed to make the change again,
+/// and nothing which can be worked out from the events before them.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    UserAdded {
+        user: User
+    },
+    UserRenamed {
+        id: UserId,
+        name: UserName
+    },
+    UserDeactivated {
+        id: UserId
+    },
+    UserReactivated {
+        id: UserId
+    },
+    UserRemoved {
+        id: UserId
+    },
+    BaseCurrencySet {
+        currency: Currency
+    },
+    RatesImported {
+        entries: Vec<RateEntry>
+    },
+    /// A new transaction, with its id and users' ids already assigned
+    TransactionAdded {
+        transaction: Transaction
+    },
+    /// The new version of an existing transaction
+    TransactionEdited {
+        #[serde(with = "crate::core::transaction::datetime_serialization")]
+        edited_at: DateTime<Utc>,
+        transaction: Transaction
+    },
+    /// A transaction undoing the one it `reverses`
+    TransactionReversed {
+        reversal: Transaction
+    },
+    RecurringAdded {
+        recurring: Recurring
+    },
+    /// The new state of a recurring transaction, after it was paused, resumed or run
+    RecurringUpdated {
+        recurring: Recurring
+    }
+}
+
+impl Event {
+    /// The transaction the event adds, edits or reverses with, if it is about one.
+    pub fn transaction(&self) -> Option<&Transaction> {
+        match self {
+            Event::TransactionAdded { transaction }
+            | Event::TransactionEdited { transaction, .. }
+            | Event::TransactionReversed { reversal: transaction } => Some(transaction),
+            _ => None
+        }
+    }
+
+    /// The transaction the event is about, to be changed, e.g. when it is given a new id.
+    pub(crate) fn transaction_mut(&mut self) -> Option<&mut Transaction> {
+        match self {
+            Event::TransactionAdded { transaction }
+            | Event::TransactionEdited { transaction, .. }
+            | Event::TransactionReversed { reversal: transaction } => Some(transaction),
+            _ => None
+        }
+    }
+}
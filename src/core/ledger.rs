@@ -1,24 +1,96 @@
 use std::collections::HashMap;
 
-use crate::core::user::{User, UserName, Amount};
+use crate::core::amount::Amount;
+use crate::core::currency::{Currency, Rate};
+use crate::core::rates::{RateEntry, RateTable};
+use crate::core::user::{self, User, UserId, UserName};
 use crate::core::transaction::{
-    Transaction, TransactionResult,
-    Benefit, AmountPerUser, BenefitPerUser, UserAmountMap};
+    Transaction, TransactionPatch, TransactionResult, Revision,
+    Benefit, AmountPerUser, BenefitPerUser, UserAmountMap, CategoryAmountMap};
 use crate::core::error::TransactionError;
+use crate::core::settlement::{self, Settlement};
+use crate::core::query::Query;
+use crate::core::recurring::{Recurring, RecurringRun, Schedule};
+use crate::core::event::Event;
 
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 
 
-type UserMap = HashMap<UserName, User>;
+type UserMap = HashMap<UserId, User>;
+/// A user's balance after each transaction which changed it, in the order they happened.
+pub type BalanceHistory = Vec<(DateTime<Utc>, Amount)>;
 
 #[derive(Serialize, Deserialize)]
+#[serde(from = "StoredLedger")]
 pub struct Ledger {
     next_id: usize,
     balances: UserAmountMap,
     users: UserMap,
     transactions: Vec<Transaction>,
-    total_spend: Amount
+    total_spend: Amount,
+    base_currency: Currency,
+    #[serde(skip_serializing_if = "RateTable::is_empty")]
+    rates: RateTable,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    revisions: Vec<Revision>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    recurring: Vec<Recurring>,
+    /// Goes up every time the ledger is saved, so that a stale copy can be told apart
+    revision: u64,
+    /// Every change made to the ledger, oldest first
+    #[serde(skip)]
+    events: Vec<Event>,
+    /// Number of `events` which were already in storage when the ledger was read
+    #[serde(skip)]
+    saved_events: usize
+}
+
+/// A ledger as read from storage, which may be in an older format.
+#[derive(Deserialize)]
+struct StoredLedger {
+    next_id: usize,
+    balances: UserAmountMap,
+    users: UserMap,
+    transactions: Vec<Transaction>,
+    total_spend: Amount,
+    #[serde(default)]
+    base_currency: Currency,
+    #[serde(default)]
+    rates: RateTable,
+    #[serde(default)]
+    revisions: Vec<Revision>,
+    #[serde(default)]
+    recurring: Vec<Recurring>,
+    #[serde(default = "first_revision")]
+    revision: u64
+}
+
+/// Ledgers saved before revisions were kept count as having been saved once.
+pub(crate) fn first_revision() -> u64 {
+    return 1;
+}
+
+impl From<StoredLedger> for Ledger {
+    fn from(stored: StoredLedger) -> Ledger {
+        let mut ledger = Ledger {
+            next_id: stored.next_id,
+            balances: stored.balances,
+            users: stored.users,
+            transactions: stored.transactions,
+            total_spend: stored.total_spend,
+            base_currency: stored.base_currency,
+            rates: stored.rates,
+            revisions: stored.revisions,
+            recurring: stored.recurring,
+            revision: stored.revision,
+            events: Vec::new(),
+            saved_events: 0
+        };
+        ledger.migrate_user_ids();
+        ledger.events = ledger.snapshot_events();
+        return ledger;
+    }
 }
 
 
@@ -26,33 +98,320 @@ impl Ledger {
     const CONSISTENCY_CHECK_INTERVAL: usize = 100;
 
     pub fn new<T: AsRef<str>>(user_names: Vec<T>) -> Ledger {
-        let users = user_names.iter()
-            .map(|user_name| (String::from(user_name.as_ref()), User::new(user_name.as_ref())))
-            .collect();
+        let mut ledger = Ledger::empty();
+        for user_name in user_names {
+            ledger.commit(Event::UserAdded { user: User::new(user_name.as_ref()) })
+                .expect("adding a user can't fail");
+        }
+        return ledger;
+    }
 
-        let balances = user_names.iter()
-            .map(|user_name| (String::from(user_name.as_ref()), 0.0 as Amount))
-            .collect();
+    fn empty() -> Ledger {
+        return Ledger { next_id: 1, balances: HashMap::new(), users: HashMap::new(), transactions: Vec::new(),
+            total_spend: Amount::ZERO, base_currency: Currency::default(), rates: RateTable::default(),
+            revisions: Vec::new(), recurring: Vec::new(), revision: 0, events: Vec::new(), saved_events: 0 };
+    }
+
+    /// Rebuilds a ledger by replaying the events which made it, oldest first.
+    /// The events are taken to be already saved, and the revision is their number.
+    pub fn from_events(events: Vec<Event>) -> TransactionResult<Ledger> {
+        let mut ledger = Ledger::empty();
+        for event in events {
+            ledger.commit(event)?;
+        }
+        ledger.mark_saved(ledger.events.len() as u64);
+        return Ok(ledger);
+    }
+
+    /// The revision the ledger was at when it was last read or saved. Zero for a new ledger.
+    pub fn revision(&self) -> u64 {
+        return self.revision;
+    }
+
+    /// Changes the revision without taking the events as saved, e.g. to write it out before the save is done.
+    pub(crate) fn set_revision(&mut self, revision: u64) {
+        self.revision = revision;
+    }
 
-        return Ledger { next_id: 1, balances, users, transactions: Vec::new(), total_spend: 0.0 };
+    /// Records that the ledger, with all its events, was saved as `revision`.
+    pub(crate) fn mark_saved(&mut self, revision: u64) {
+        self.revision = revision;
+        self.saved_events = self.events.len();
+    }
+
+    /// Every change made to the ledger, oldest first.
+    pub fn events(&self) -> &[Event] {
+        return &self.events;
+    }
+
+    /// The events which happened since the ledger was read from storage.
+    pub fn unsaved_events(&self) -> &[Event] {
+        return &self.events[self.saved_events..];
     }
 
     pub fn get_users(&self) -> Vec<&User> {
         return self.users.values().collect();
     }
 
+    pub fn get_user(&self, id: &str) -> Option<&User> {
+        return self.users.get(id);
+    }
+
+    /// Finds a user by id or, failing that, by name.
+    /// A name shared by several users is ambiguous and can't be used.
+    pub fn find_user(&self, reference: &str) -> TransactionResult<&User> {
+        if let Some(user) = self.users.get(reference) {
+            return Ok(user);
+        }
+        let mut named = self.users.values().filter(|user| user.name == reference);
+        match (named.next(), named.next()) {
+            (Some(user), None) => Ok(user),
+            (Some(_), Some(_)) => Err(TransactionError::AmbiguousUser(reference.to_owned())),
+            (None, _) => Err(TransactionError::UnknownUser(reference.to_owned()))
+        }
+    }
+
+    /// The name of the user with the given id, followed by
+    /// the id itself if other users have the same name.
+    pub fn display_name(&self, id: &str) -> String {
+        match self.users.get(id) {
+            None => id.to_owned(),
+            Some(user) => {
+                let same_name = self.users.values().filter(|other| other.name == user.name).count();
+                if same_name > 1 {
+                    format!("{} ({})", user.name, user.id)
+                } else {
+                    user.name.clone()
+                }
+            }
+        }
+    }
+
+    /// A copy of the transaction with user ids replaced by display names.
+    pub fn with_display_names(&self, transaction: &Transaction) -> Transaction {
+        let mut named = transaction.clone();
+        let ids: Vec<UserId> = transaction.users().cloned().collect();
+        for id in ids {
+            named.rename_user(&id, &self.display_name(&id));
+        }
+        return named;
+    }
+
     pub fn get_balances(&self) -> UserAmountMap {
         return self.balances.iter()
             .map(|pair| (pair.0.to_owned(), pair.1.to_owned()))
             .collect();
     }
 
+    /// Balances as they were at `time`, counting the transactions
+    /// which happened up to and including then.
+    pub fn balances_at(&self, time: DateTime<Utc>) -> TransactionResult<UserAmountMap> {
+        let mut balances: UserAmountMap =
+            self.balances.keys().map(|user| (user.clone(), Amount::ZERO)).collect();
+        let mut total = Amount::ZERO;
+
+        for transaction in self.transactions_by_time().into_iter().take_while(|t| t.datetime <= time) {
+            let rate = self.exchange_rate(transaction)?;
+            Ledger::apply_transaction(&mut total, &mut balances, transaction, rate)?;
+        }
+        return Ok(balances);
+    }
+
+    /// Each user's balance over time.
+    pub fn balance_history(&self) -> TransactionResult<HashMap<UserId, BalanceHistory>> {
+        let mut balances: UserAmountMap =
+            self.balances.keys().map(|user| (user.clone(), Amount::ZERO)).collect();
+        let mut history: HashMap<UserId, BalanceHistory> =
+            self.balances.keys().map(|user| (user.clone(), Vec::new())).collect();
+
+        for transaction in self.transactions_by_time() {
+            let rate = self.exchange_rate(transaction)?;
+            for (user, delta) in transaction.converted_balance_updates(rate)? {
+                let balance = match balances.get_mut(&user) {
+                    Some(balance) => balance,
+                    None => return Err(TransactionError::UnknownUser(user))
+                };
+                *balance = balance.checked_add(delta).ok_or(TransactionError::AmountOverflow)?;
+                history.entry(user).or_default().push((transaction.datetime, *balance));
+            }
+        }
+        return Ok(history);
+    }
+
     pub fn get_transactions(&self) -> &Vec<Transaction> {
         return &self.transactions;
     }
 
-    pub fn add_user(&mut self, name: &str) {
-        self.users.insert(name.to_owned(), User::new(name));
+    /// Starts a query over the transactions, see [`Query`].
+    pub fn query(&self) -> Query<'_> {
+        return Query::new(self);
+    }
+
+    pub fn get_base_currency(&self) -> &Currency {
+        return &self.base_currency;
+    }
+
+    /// Sets the currency balances are reported in. Transactions without a
+    /// currency of their own are taken to be in it, and exchange rates are to
+    /// it, so it can't be changed once any of those are recorded.
+    pub fn set_base_currency(&mut self, currency: Currency) -> TransactionResult<()> {
+        if !self.transactions.is_empty() || !self.recurring.is_empty() || !self.rates.is_empty() {
+            return Err(TransactionError::BaseCurrencyInUse(self.base_currency.clone()));
+        }
+        return self.commit(Event::BaseCurrencySet { currency });
+    }
+
+    pub fn get_rates(&self) -> &RateTable {
+        return &self.rates;
+    }
+
+    /// Adds exchange rates to the base currency. Transactions which have
+    /// no rate of their own are converted at the rate in effect on their date.
+    pub fn import_rates(&mut self, entries: Vec<RateEntry>) -> TransactionResult<()> {
+        return self.commit(Event::RatesImported { entries });
+    }
+
+    /// Balances computed separately for each currency transactions were made in,
+    /// without any conversion.
+    pub fn get_balances_by_currency(&self) -> TransactionResult<HashMap<Currency, UserAmountMap>> {
+        let mut per_currency: HashMap<Currency, UserAmountMap> = HashMap::new();
+        for transaction in &self.transactions {
+            let currency = transaction.currency.as_ref().unwrap_or(&self.base_currency);
+            let balances = per_currency.entry(currency.clone()).or_insert_with(|| {
+                self.balances.keys().map(|user| (user.clone(), Amount::ZERO)).collect()
+            });
+            Ledger::update_balances(balances, transaction.balance_updates()?)?;
+        }
+        return Ok(per_currency);
+    }
+
+    /// Total spent on each category, in the base currency.
+    pub fn spending_by_category(&self) -> TransactionResult<CategoryAmountMap> {
+        let mut totals = CategoryAmountMap::new();
+        for transaction in &self.transactions {
+            if transaction.is_direct {
+                continue;
+            }
+            let rate = self.exchange_rate(transaction)?;
+            let total = totals.entry(transaction.category.clone()).or_default();
+            *total = total.checked_add(transaction.converted_spending(rate)?).ok_or(TransactionError::AmountOverflow)?;
+        }
+        return Ok(totals);
+    }
+
+    /// How much each user consumed in each category, in the base currency.
+    pub fn spending_by_user_and_category(&self) -> TransactionResult<HashMap<UserId, CategoryAmountMap>> {
+        let mut totals: HashMap<UserId, CategoryAmountMap> = HashMap::new();
+        for transaction in &self.transactions {
+            let rate = self.exchange_rate(transaction)?;
+            for (user, amount) in transaction.converted_consumption(rate)? {
+                *totals.entry(user).or_default()
+                    .entry(transaction.category.clone()).or_default() += amount;
+            }
+        }
+        return Ok(totals);
+    }
+
+    /// Lists the direct transfers which would bring all balances to zero.
+    pub fn settlement_plan(&self) -> Vec<Settlement> {
+        return settlement::plan(&self.balances);
+    }
+
+    /// Records a direct transfer from a settlement plan. Unlike other transactions,
+    /// it can involve deactivated users: an undo or an edit can leave them owing
+    /// or being owed money, which has to be settled all the same.
+    pub fn record_settlement(&mut self, settlement: &Settlement, description: &str,
+        time: Option<DateTime<Utc>>) -> TransactionResult<()> {
+        let mut transaction = Transaction::new(
+            vec![(&settlement.from, settlement.amount)],
+            vec![(&settlement.to, Benefit::Sum(settlement.amount))],
+            description,
+            true,
+            None,
+            time
+        );
+        self.resolve_users(&mut transaction)?;
+        self.check_and_number(&mut transaction)?;
+        return self.commit(Event::TransactionAdded { transaction });
+    }
+
+    /// Adds a new user and returns their id. Unless `allow_duplicate_name`
+    /// is set, the name must not be used by any other user.
+    pub fn add_user(&mut self, name: &str, allow_duplicate_name: bool) -> TransactionResult<UserId> {
+        if !allow_duplicate_name && self.users.values().any(|user| user.name == name) {
+            return Err(TransactionError::DuplicateUser(name.to_owned()));
+        }
+        let user = User::new(name);
+        let id = user.id.clone();
+        self.commit(Event::UserAdded { user })?;
+        return Ok(id);
+    }
+
+    /// Stops the user from taking part in new transactions, while keeping
+    /// them and their history on the ledger. Only possible for users with
+    /// a zero balance, as they couldn't be paid or pay back otherwise.
+    pub fn deactivate_user(&mut self, user: &str) -> TransactionResult<()> {
+        let id = self.find_user(user)?.id.clone();
+        self.check_zero_balance(user, &id)?;
+        return self.set_user_active(user, false);
+    }
+
+    pub fn reactivate_user(&mut self, user: &str) -> TransactionResult<()> {
+        return self.set_user_active(user, true);
+    }
+
+    /// Removes a user from the ledger. Only possible for users with a
+    /// zero balance who are not part of any transaction, recurring or not.
+    pub fn remove_user(&mut self, user: &str) -> TransactionResult<()> {
+        let id = self.find_user(user)?.id.clone();
+        self.check_zero_balance(user, &id)?;
+        if self.transactions.iter().any(|t| t.involves(&id)) {
+            return Err(TransactionError::UserHasTransactions(user.to_owned()));
+        }
+        if self.recurring.iter().any(|r| r.template.involves(&id)) {
+            return Err(TransactionError::UserHasRecurring(user.to_owned()));
+        }
+        return self.commit(Event::UserRemoved { id });
+    }
+
+    /// Changes a user's name. Their id, and so all past
+    /// transactions, stay the same.
+    pub fn rename_user(&mut self, user: &str, new_name: &str) -> TransactionResult<()> {
+        let id = self.find_user(user)?.id.clone();
+        if self.users.values().any(|other| other.name == new_name && other.id != id) {
+            return Err(TransactionError::DuplicateUser(new_name.to_owned()));
+        }
+        return self.commit(Event::UserRenamed { id, name: new_name.to_owned() });
+    }
+
+    fn set_user_active(&mut self, user: &str, active: bool) -> TransactionResult<()> {
+        let id = self.find_user(user)?.id.clone();
+        if active {
+            return self.commit(Event::UserReactivated { id });
+        }
+        return self.commit(Event::UserDeactivated { id });
+    }
+
+    /// Ledgers written before user ids existed are keyed by user name.
+    /// Gives each of their users a new id, and rewrites all references to them.
+    fn migrate_user_ids(&mut self) {
+        let legacy_keys: Vec<UserName> = self.users.iter()
+            .filter(|(_, user)| user.id.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in legacy_keys {
+            if let Some(mut user) = self.users.remove(&key) {
+                user.id = user::generate_user_id();
+                if let Some(balance) = self.balances.remove(&key) {
+                    self.balances.insert(user.id.clone(), balance);
+                }
+                for transaction in &mut self.transactions {
+                    transaction.rename_user(&key, &user.id);
+                }
+                self.users.insert(user.id.clone(), user);
+            }
+        }
     }
 
     pub fn add_expense(&mut self, contributions: AmountPerUser<&str>, benefits: BenefitPerUser<&str>,
@@ -80,55 +439,353 @@ impl Ledger {
         self.add_transaction(transaction)
     }
 
+    /// Records a transaction. Its users can be given either by id or by name.
     pub fn add_transaction(&mut self, mut transaction: Transaction) -> TransactionResult<()> {
-        Ledger::apply_transaction(&mut self.total_spend, &mut self.balances, &transaction)?;
-        self.assign_transaction_id(&mut transaction);
-        self.transactions.push(transaction);
+        self.prepare_transaction(&mut transaction)?;
+        return self.commit(Event::TransactionAdded { transaction });
+    }
 
-        if self.needs_consistency_check() {
-            self.reapply_all()?;
-        }
+    /// Checks a new transaction can be recorded, and gives it an id.
+    fn prepare_transaction(&self, transaction: &mut Transaction) -> TransactionResult<()> {
+        self.resolve_users(transaction)?;
+        self.check_users_active(transaction)?;
+        return self.check_and_number(transaction);
+    }
+
+    /// Checks the transaction's amounts can be worked out, and gives it the next id.
+    fn check_and_number(&self, transaction: &mut Transaction) -> TransactionResult<()> {
+        transaction.balance_updates()?;
+        self.exchange_rate(transaction)?;
+        transaction.id = self.next_id;
         return Ok(());
     }
 
+    /// Undoes a transaction by recording its reverse. Each transaction
+    /// can be undone once, and undoing an undo is not allowed.
     pub fn reverse_by_id(&mut self, id: usize) -> TransactionResult<()> {
-        let found = self.transactions.iter().find(|&t| { t.id == id });
-        match found {
-            None => Err(TransactionError::UnknownTransactionId(id)),
-            Some(transaction) => self.add_transaction(transaction.reverse()?)
+        let transaction = self.find_reversible(id)?;
+        let mut reversal = transaction.reverse()?;
+        // freeze the rate, so the undo matches even if the rate table changes later
+        if reversal.currency.is_some() {
+            reversal.rate = Some(self.exchange_rate(transaction)?);
         }
+        // the users may have been deactivated since, but an undo only cancels out history
+        self.check_and_number(&mut reversal)?;
+        return self.commit(Event::TransactionReversed { reversal });
     }
 
-    fn assign_transaction_id(&mut self, transaction: &mut Transaction) {
-        transaction.id = self.next_id;
-        self.next_id += 1;
+    /// The transaction which undoes the one with the given id, if it has been undone.
+    pub fn get_reversal(&self, id: usize) -> Option<&Transaction> {
+        return self.transactions.iter().find(|t| t.reverses == Some(id));
     }
 
-    fn apply_transaction(total_spend: &mut Amount, balances: &mut UserAmountMap, transaction: &Transaction) -> TransactionResult<()> {
-        if !transaction.is_direct {
-            *total_spend += transaction.total_spending();
+    /// A transaction which can still be undone or edited.
+    fn find_reversible(&self, id: usize) -> TransactionResult<&Transaction> {
+        let transaction = match self.transactions.iter().find(|t| t.id == id) {
+            Some(transaction) => transaction,
+            None => return Err(TransactionError::UnknownTransactionId(id))
+        };
+        if transaction.reverses.is_some() {
+            return Err(TransactionError::IsReversal(id));
+        }
+        if self.get_reversal(id).is_some() {
+            return Err(TransactionError::AlreadyReversed(id));
         }
-        let balance_updates = transaction.balance_updates()?;
-        return Ledger::update_balances(balances, balance_updates);
+        return Ok(transaction);
     }
 
-    fn update_balances(balances: &mut UserAmountMap, changes: UserAmountMap) -> TransactionResult<()> {
-        for (user, delta) in &changes {
-            match balances.get_mut(user) {
-                Some(val) => *val += delta,
-                None => return Err(TransactionError::UnknownUser(user.clone()))
+    /// Changes an existing transaction and recalculates all balances.
+    /// The previous version is kept, and can be found with [`Ledger::get_revisions`].
+    pub fn edit_transaction(&mut self, id: usize, patch: TransactionPatch) -> TransactionResult<()> {
+        self.find_reversible(id)?;
+        let idx = match self.transactions.iter().position(|t| t.id == id) {
+            Some(idx) => idx,
+            None => return Err(TransactionError::UnknownTransactionId(id))
+        };
+        if patch.is_empty() {
+            return Ok(());
+        }
+
+        let mut edited = patch.apply(&self.transactions[idx]);
+        self.resolve_users(&mut edited)?;
+        // users who have since been deactivated can stay on the transaction, but not be added to it
+        for user in edited.users() {
+            if !self.transactions[idx].involves(user) {
+                self.check_user_active(user)?;
+            }
+        }
+        edited.balance_updates()?;
+        self.exchange_rate(&edited)?;
+        return self.commit(Event::TransactionEdited { edited_at: Utc::now(), transaction: edited });
+    }
+
+    /// Previous versions of a transaction, oldest first.
+    pub fn get_revisions(&self, id: usize) -> Vec<&Revision> {
+        return self.revisions.iter().filter(|r| r.transaction.id == id).collect();
+    }
+
+    /// Adds a transaction to be repeated on `schedule`, from `start` until `end` if given.
+    /// The repetitions are created by [`Ledger::materialize_recurring`]. Returns the id of the new item.
+    pub fn add_recurring(&mut self, mut template: Transaction, schedule: Schedule,
+        start: NaiveDate, end: Option<NaiveDate>) -> TransactionResult<usize> {
+        self.resolve_users(&mut template)?;
+        self.check_users_active(&template)?;
+        template.balance_updates()?;
+        template.id = 0;
+
+        let id = self.recurring.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+        let recurring = Recurring { id, template, schedule, start, end, paused: false, done_until: None };
+        self.commit(Event::RecurringAdded { recurring })?;
+        return Ok(id);
+    }
+
+    pub fn get_recurring(&self) -> &Vec<Recurring> {
+        return &self.recurring;
+    }
+
+    /// Stops a recurring transaction from being repeated, until it is resumed.
+    pub fn pause_recurring(&mut self, id: usize) -> TransactionResult<()> {
+        let mut recurring = self.find_recurring(id)?.clone();
+        recurring.paused = true;
+        return self.commit(Event::RecurringUpdated { recurring });
+    }
+
+    /// Repeats a paused transaction again from the day `from`.
+    /// Repetitions which were due while it was paused are skipped.
+    pub fn resume_recurring(&mut self, id: usize, from: NaiveDate) -> TransactionResult<()> {
+        let mut recurring = self.find_recurring(id)?.clone();
+        recurring.paused = false;
+        let skipped_until = from.pred();
+        if recurring.done_until.is_none_or(|done| done < skipped_until) {
+            recurring.done_until = Some(skipped_until);
+        }
+        return self.commit(Event::RecurringUpdated { recurring });
+    }
+
+    /// Creates the transactions which are due up to and including the day `until`,
+    /// at noon UTC on the day. Each repetition is only ever created once, so this
+    /// can safely be run again. A recurring transaction which fails, e.g. because
+    /// one of its users was deactivated, is skipped without holding up the others.
+    pub fn materialize_recurring(&mut self, until: NaiveDate) -> RecurringRun {
+        let mut due: Vec<(NaiveDate, usize)> = self.recurring.iter().enumerate()
+            .flat_map(|(idx, recurring)| recurring.due_dates(until).into_iter().map(move |date| (date, idx)))
+            .collect();
+        due.sort();
+
+        let mut run = RecurringRun::default();
+        for (date, idx) in due {
+            let id = self.recurring[idx].id;
+            if run.failed.iter().any(|(failed, _)| *failed == id) {
+                continue;
+            }
+            let mut transaction = self.recurring[idx].template.clone();
+            transaction.datetime = Utc.from_utc_datetime(&date.and_hms(12, 0, 0));
+            let mut recurring = self.recurring[idx].clone();
+            recurring.done_until = Some(date);
+            match self.add_transaction(transaction).and_then(|()| self.commit(Event::RecurringUpdated { recurring })) {
+                Ok(()) => run.created += 1,
+                Err(err) => run.failed.push((id, err))
+            }
+        }
+        return run;
+    }
+
+    fn find_recurring(&self, id: usize) -> TransactionResult<&Recurring> {
+        return self.recurring.iter().find(|r| r.id == id)
+            .ok_or(TransactionError::UnknownRecurringId(id));
+    }
+
+    /// Makes the change described by the event, and adds it to the log if it succeeds.
+    fn commit(&mut self, event: Event) -> TransactionResult<()> {
+        self.apply_event(&event)?;
+        self.events.push(event);
+        return Ok(());
+    }
+
+    fn apply_event(&mut self, event: &Event) -> TransactionResult<()> {
+        match event {
+            Event::UserAdded { user } => {
+                self.users.insert(user.id.clone(), user.clone());
+                self.balances.insert(user.id.clone(), Amount::ZERO);
+            },
+            Event::UserRenamed { id, name } => self.find_user_mut(id)?.name = name.clone(),
+            Event::UserDeactivated { id } => self.find_user_mut(id)?.active = false,
+            Event::UserReactivated { id } => self.find_user_mut(id)?.active = true,
+            Event::UserRemoved { id } => {
+                self.users.remove(id);
+                self.balances.remove(id);
+            },
+            Event::BaseCurrencySet { currency } => {
+                let previous = std::mem::replace(&mut self.base_currency, currency.clone());
+                if let Err(err) = self.reapply_all() {
+                    self.base_currency = previous;
+                    return Err(err);
+                }
+            },
+            Event::RatesImported { entries } => {
+                let previous = self.rates.clone();
+                for entry in entries {
+                    self.rates.insert(entry.clone());
+                }
+                if let Err(err) = self.reapply_all() {
+                    self.rates = previous;
+                    return Err(err);
+                }
+            },
+            Event::TransactionAdded { transaction } | Event::TransactionReversed { reversal: transaction } => {
+                let rate = self.exchange_rate(transaction)?;
+                self.transactions.push(transaction.clone());
+
+                // every so often, work out all balances again instead of just adding this one
+                let applied = if self.needs_consistency_check() {
+                    self.reapply_all()
+                } else {
+                    Ledger::apply_transaction(&mut self.total_spend, &mut self.balances, transaction, rate)
+                };
+                if let Err(err) = applied {
+                    self.transactions.pop();
+                    return Err(err);
+                }
+                self.next_id = self.next_id.max(transaction.id + 1);
+            },
+            Event::TransactionEdited { edited_at, transaction } => {
+                let idx = match self.transactions.iter().position(|t| t.id == transaction.id) {
+                    Some(idx) => idx,
+                    None => return Err(TransactionError::UnknownTransactionId(transaction.id))
+                };
+                let previous = std::mem::replace(&mut self.transactions[idx], transaction.clone());
+                if let Err(err) = self.reapply_all() {
+                    self.transactions[idx] = previous;
+                    return Err(err);
+                }
+                self.revisions.push(Revision { edited_at: *edited_at, transaction: previous });
+            },
+            Event::RecurringAdded { recurring } => self.recurring.push(recurring.clone()),
+            Event::RecurringUpdated { recurring } => {
+                match self.recurring.iter_mut().find(|r| r.id == recurring.id) {
+                    Some(existing) => *existing = recurring.clone(),
+                    None => return Err(TransactionError::UnknownRecurringId(recurring.id))
+                }
             }
         }
         return Ok(());
     }
 
+    fn find_user_mut(&mut self, id: &str) -> TransactionResult<&mut User> {
+        return self.users.get_mut(id).ok_or_else(|| TransactionError::UnknownUser(id.to_owned()));
+    }
+
+    /// Events which rebuild the ledger as it is, for ledgers read from a
+    /// snapshot. The history before the snapshot is only kept as far as the
+    /// snapshot has it: users are added as they are now, and each transaction
+    /// is added followed by its edits.
+    fn snapshot_events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut users: Vec<&User> = self.users.values().collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        events.extend(users.into_iter().map(|user| Event::UserAdded { user: user.clone() }));
+        if self.base_currency != Currency::default() {
+            events.push(Event::BaseCurrencySet { currency: self.base_currency.clone() });
+        }
+        if !self.rates.is_empty() {
+            events.push(Event::RatesImported { entries: self.rates.entries() });
+        }
+
+        for transaction in &self.transactions {
+            let revisions = self.get_revisions(transaction.id);
+            let mut versions = revisions.iter().map(|r| &r.transaction).chain(std::iter::once(transaction));
+            let first = versions.next().unwrap_or(transaction).clone();
+            events.push(match first.reverses {
+                Some(_) => Event::TransactionReversed { reversal: first },
+                None => Event::TransactionAdded { transaction: first }
+            });
+            for (revision, version) in revisions.iter().zip(versions) {
+                events.push(Event::TransactionEdited { edited_at: revision.edited_at, transaction: version.clone() });
+            }
+        }
+        events.extend(self.recurring.iter().map(|recurring| Event::RecurringAdded { recurring: recurring.clone() }));
+        return events;
+    }
+
+    /// Replaces the users referred to by name in the transaction with their ids.
+    fn resolve_users(&self, transaction: &mut Transaction) -> TransactionResult<()> {
+        let references: Vec<UserId> = transaction.users().cloned().collect();
+        for reference in references {
+            let id = self.find_user(&reference)?.id.clone();
+            if id != reference {
+                transaction.rename_user(&reference, &id);
+            }
+        }
+        return Ok(());
+    }
+
+    fn check_zero_balance(&self, user: &str, id: &str) -> TransactionResult<()> {
+        let balance = self.balances.get(id).copied().unwrap_or(Amount::ZERO);
+        if !balance.is_zero() {
+            return Err(TransactionError::UserHasBalance(user.to_owned(), balance));
+        }
+        return Ok(());
+    }
+
+    fn check_users_active(&self, transaction: &Transaction) -> TransactionResult<()> {
+        for id in transaction.users() {
+            self.check_user_active(id)?;
+        }
+        return Ok(());
+    }
+
+    fn check_user_active(&self, id: &str) -> TransactionResult<()> {
+        match self.users.get(id) {
+            Some(user) if !user.active => Err(TransactionError::InactiveUser(user.name.clone())),
+            _ => Ok(())
+        }
+    }
+
+    /// The rate converting the transaction's amounts to the base currency.
+    /// A rate recorded on the transaction takes precedence over the rate table.
+    pub(crate) fn exchange_rate(&self, transaction: &Transaction) -> TransactionResult<Rate> {
+        match (&transaction.currency, transaction.rate) {
+            (None, _) => Ok(Rate::ONE),
+            (Some(currency), _) if *currency == self.base_currency => Ok(Rate::ONE),
+            (Some(_), Some(rate)) => Ok(rate),
+            (Some(currency), None) => self.rates.rate_at(currency, transaction.datetime)
+                .ok_or_else(|| TransactionError::MissingExchangeRate(currency.clone()))
+        }
+    }
+
+    fn apply_transaction(total_spend: &mut Amount, balances: &mut UserAmountMap,
+        transaction: &Transaction, rate: Rate) -> TransactionResult<()> {
+        let balance_updates = transaction.converted_balance_updates(rate)?;
+        let new_total = total_spend.checked_add(transaction.converted_spending(rate)?)
+            .ok_or(TransactionError::AmountOverflow)?;
+        Ledger::update_balances(balances, balance_updates)?;
+        *total_spend = new_total;
+        return Ok(());
+    }
+
+    /// Adds the changes to the balances, or leaves them as they are if any of the
+    /// users is unknown or a balance would grow too large to represent.
+    fn update_balances(balances: &mut UserAmountMap, changes: UserAmountMap) -> TransactionResult<()> {
+        let mut updated = Vec::with_capacity(changes.len());
+        for (user, delta) in changes {
+            let balance = match balances.get(&user) {
+                Some(balance) => *balance,
+                None => return Err(TransactionError::UnknownUser(user))
+            };
+            updated.push((user, balance.checked_add(delta).ok_or(TransactionError::AmountOverflow)?));
+        }
+        balances.extend(updated);
+        return Ok(());
+    }
+
     fn reapply_all(&mut self) -> TransactionResult<()> {
         let mut new_balances: UserAmountMap =
-            self.balances.keys().map(|user| (user.clone(), 0.0)).collect();
-        let mut new_total: Amount = 0.0;
+            self.balances.keys().map(|user| (user.clone(), Amount::ZERO)).collect();
+        let mut new_total = Amount::ZERO;
 
         for transaction in &self.transactions {
-            Ledger::apply_transaction(&mut new_total, &mut new_balances, transaction)?;
+            let rate = self.exchange_rate(transaction)?;
+            Ledger::apply_transaction(&mut new_total, &mut new_balances, transaction, rate)?;
         }
 
         self.total_spend = new_total;
@@ -136,16 +793,30 @@ impl Ledger {
         return Ok(());
     }
 
+    /// Transactions in the order they happened, rather than the order they were recorded.
+    fn transactions_by_time(&self) -> Vec<&Transaction> {
+        let mut ordered: Vec<&Transaction> = self.transactions.iter().collect();
+        // stable, so transactions at the same time stay in the order they were recorded
+        ordered.sort_by_key(|t| t.datetime);
+        return ordered;
+    }
+
     fn needs_consistency_check(&self) -> bool {
-        return self.transactions.len() % Self::CONSISTENCY_CHECK_INTERVAL == 0;
+        return self.transactions.len().is_multiple_of(Self::CONSISTENCY_CHECK_INTERVAL);
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::core::{Ledger, User, UserName};
-    use crate::core::transaction::Benefit;
+    use crate::core::{Amount, Ledger, Transaction, UserName};
+    use crate::core::transaction::{Benefit, TransactionPatch, TransactionResult};
+    use crate::core::currency::Currency;
+    use crate::core::event::Event;
+    use crate::core::rates::RateEntry;
+    use crate::core::receipt::{Item, Surcharge};
+    use crate::core::recurring::Schedule;
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
     use crate::core::error::TransactionError;
     use crate::transaction::{AmountPerUser, BenefitPerUser};
 
@@ -174,24 +845,27 @@ mod tests {
 
         let users = ledger.get_users();
 
-        assert_eq!(users.len(), 4);
-        assert!(users.contains(&&User::new(&bilbo)));
-        assert!(users.contains(&&User::new(&frodo)));
-        assert!(users.contains(&&User::new(&legolas)));
-        assert!(users.contains(&&User::new(&gimli)));
-        assert!(!users.contains(&&User::new("Merry")));
+        let names: Vec<&str> = users.iter().map(|user| user.name.as_str()).collect();
+
+        assert_eq!(names.len(), 4);
+        assert!(names.contains(&bilbo.as_str()));
+        assert!(names.contains(&frodo.as_str()));
+        assert!(names.contains(&legolas.as_str()));
+        assert!(names.contains(&gimli.as_str()));
+        assert!(!names.contains(&"Merry"));
+        assert!(users.iter().all(|user| ledger.get_user(&user.id) == Some(user)));
     }
 
     #[rstest]
     fn simple_transfer(mut ledger: Ledger, user_names: UserNames4) {
         let (bilbo, frodo, _, gimli) = user_names;
 
-        ledger.add_transfer(&bilbo, &frodo, 32.0, "", None).unwrap();
+        ledger.add_transfer(&bilbo, &frodo, Amount::from_major(32), "", None).unwrap();
 
-        assert_eq!(ledger.total_spend, 0.0);
-        assert_eq!(*ledger.balances.get(&bilbo).unwrap(), 32.0);
-        assert_eq!(*ledger.balances.get(&frodo).unwrap(), -32.0);
-        assert_eq!(*ledger.balances.get(&gimli).unwrap(), 0.0);
+        assert_eq!(ledger.total_spend, Amount::ZERO);
+        assert_eq!(balance(&ledger, &bilbo), Amount::from_major(32));
+        assert_eq!(balance(&ledger, &frodo), Amount::from_major(-32));
+        assert_eq!(balance(&ledger, &gimli), Amount::ZERO);
     }
 
     #[rstest]
@@ -199,15 +873,122 @@ mod tests {
         let bilbo = user_names.0;
         let merry = String::from("Merry");
 
-        let res = ledger.add_transfer(&bilbo, &merry, 32.0, "", None);
+        let res = ledger.add_transfer(&bilbo, &merry, Amount::from_major(32), "", None);
 
         assert!(res.is_err());
         assert!(matches!(res, Err(TransactionError::UnknownUser(..))));
     }
 
+    #[rstest]
+    fn add_user_then_transact(mut ledger: Ledger, user_names: UserNames4) {
+        let bilbo = &user_names.0;
+
+        ledger.add_user("Merry", false).unwrap();
+        ledger.add_transfer(bilbo, "Merry", Amount::from_major(5), "", None).unwrap();
+
+        assert_eq!(balance(&ledger, "Merry"), Amount::from_major(-5));
+    }
+
+    #[rstest]
+    fn add_duplicate_user(mut ledger: Ledger) {
+        let res = ledger.add_user("Frodo", false);
+
+        assert!(matches!(res, Err(TransactionError::DuplicateUser(..))));
+        assert_eq!(ledger.get_users().len(), 4);
+    }
+
+    #[rstest]
+    fn users_with_same_name(mut ledger: Ledger, user_names: UserNames4) {
+        let bilbo = &user_names.0;
+        let first_frodo = ledger.find_user("Frodo").unwrap().id.clone();
+        let second_frodo = ledger.add_user("Frodo", true).unwrap();
+
+        assert_ne!(first_frodo, second_frodo);
+        let res = ledger.add_transfer(bilbo, "Frodo", Amount::from_major(5), "", None);
+        assert!(matches!(res, Err(TransactionError::AmbiguousUser(..))));
+
+        ledger.add_transfer(bilbo, &second_frodo, Amount::from_major(5), "", None).unwrap();
+        assert_eq!(balance(&ledger, &second_frodo), Amount::from_major(-5));
+        assert_eq!(balance(&ledger, &first_frodo), Amount::ZERO);
+        assert_eq!(ledger.display_name(&second_frodo), format!("Frodo ({})", second_frodo));
+        assert_eq!(ledger.display_name(&ledger.find_user(bilbo).unwrap().id), "Bilbo");
+    }
+
+    #[rstest]
+    fn deactivated_user(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+
+        // only once they have settled up
+        let res = ledger.deactivate_user(frodo);
+        assert!(matches!(res, Err(TransactionError::UserHasBalance(..))));
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", None).unwrap();
+        ledger.deactivate_user(frodo).unwrap();
+
+        let res = ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", None);
+        assert!(matches!(res, Err(TransactionError::InactiveUser(..))));
+        assert_eq!(ledger.transactions.len(), 2);
+
+        // history involving the user is still replayed
+        ledger.reapply_all().unwrap();
+        assert!(balance(&ledger, frodo).is_zero());
+
+        ledger.reactivate_user(frodo).unwrap();
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", None).unwrap();
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(20));
+    }
+
+    #[rstest]
+    fn remove_user(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, gimli) = &user_names;
+        ledger.add_transfer(bilbo, frodo, Amount::from_major(5), "", None).unwrap();
+
+        assert!(matches!(ledger.remove_user(frodo), Err(TransactionError::UserHasBalance(..))));
+
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(5), "", None).unwrap();
+        assert!(matches!(ledger.remove_user(frodo), Err(TransactionError::UserHasTransactions(..))));
+        assert!(matches!(ledger.remove_user("Merry"), Err(TransactionError::UnknownUser(..))));
+
+        ledger.remove_user(gimli).unwrap();
+        assert_eq!(ledger.get_users().len(), 3);
+        assert!(!ledger.get_balances().contains_key(gimli));
+    }
+
+    #[rstest]
+    fn rename_user(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, legolas, gimli) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+
+        let frodo_id = ledger.find_user(frodo).unwrap().id.clone();
+        ledger.rename_user(frodo, "Mr Underhill").unwrap();
+
+        assert!(matches!(ledger.find_user(frodo), Err(TransactionError::UnknownUser(..))));
+        assert_eq!(ledger.find_user("Mr Underhill").unwrap().id, frodo_id);
+        assert_eq!(balance(&ledger, "Mr Underhill"), Amount::from_major(-2));
+        assert!(ledger.transactions.iter().all(|t| t.involves(&frodo_id)));
+
+        let listed = ledger.with_display_names(&ledger.transactions[1]);
+        assert!(listed.involves("Mr Underhill") && !listed.involves(&frodo_id));
+
+        ledger.reapply_all().unwrap();
+        assert_eq!(balance(&ledger, "Mr Underhill"), Amount::from_major(-2));
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(40));
+
+        assert!(matches!(ledger.rename_user(legolas, gimli), Err(TransactionError::DuplicateUser(..))));
+        assert!(matches!(ledger.rename_user(frodo, "Sam"), Err(TransactionError::UnknownUser(..))));
+        ledger.rename_user(&frodo_id, "Frodo").unwrap();
+        assert_eq!(ledger.get_user(&frodo_id).unwrap().name, "Frodo");
+    }
+
+    fn balance(ledger: &Ledger, user: &str) -> Amount {
+        let id = &ledger.find_user(user).unwrap().id;
+        return ledger.balances[id];
+    }
+
     fn add_transaction_bilbo(ledger: &mut Ledger, user_names: &UserNames4) {
         let (bilbo, frodo, legolas, _) = user_names;
-        let contributions: AmountPerUser<&str> = vec![(bilbo, 60.0)];
+        let contributions: AmountPerUser<&str> = vec![(bilbo, Amount::from_major(60))];
         let benefits: BenefitPerUser<&str> = vec![
             (frodo, Benefit::Even),
             (legolas, Benefit::Even),
@@ -218,10 +999,10 @@ mod tests {
 
     fn add_transaction_frodo(ledger: &mut Ledger, user_names: &UserNames4) {
         let (_, frodo, legolas, gimli) = user_names;
-        let contributions: AmountPerUser<&str> = vec![(frodo, 30.0)];
+        let contributions: AmountPerUser<&str> = vec![(frodo, Amount::from_major(30))];
         let benefits: BenefitPerUser<&str> = vec![
             (frodo, Benefit::Even),
-            (legolas, Benefit::Sum(6.0)),
+            (legolas, Benefit::Sum(Amount::from_major(6))),
             (gimli, Benefit::Even)
         ];
         ledger.add_expense(contributions, benefits, "", None).unwrap()
@@ -235,11 +1016,418 @@ mod tests {
         add_transaction_frodo(&mut ledger, &user_names);
 
         assert_eq!(ledger.transactions.len(), 2);
-        assert_eq!(ledger.total_spend, 90.0);
-        assert_eq!(*ledger.balances.get(bilbo).unwrap(), 40.0);
-        assert_eq!(*ledger.balances.get(frodo).unwrap(), -2.0);
-        assert_eq!(*ledger.balances.get(legolas).unwrap(), -26.0);
-        assert_eq!(*ledger.balances.get(gimli).unwrap(), -12.0);
+        assert_eq!(ledger.total_spend, Amount::from_major(90));
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(40));
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-2));
+        assert_eq!(balance(&ledger, legolas), Amount::from_major(-26));
+        assert_eq!(balance(&ledger, gimli), Amount::from_major(-12));
+    }
+
+    #[rstest]
+    fn settle_up(mut ledger: Ledger, user_names: UserNames4) {
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+
+        for s in ledger.settlement_plan() {
+            ledger.add_transfer(&s.from, &s.to, s.amount, "", None).unwrap();
+        }
+
+        assert!(ledger.balances.values().all(|b| b.is_zero()));
+        assert!(ledger.settlement_plan().is_empty());
+        assert_eq!(ledger.total_spend, Amount::from_major(90));
+    }
+
+    #[rstest]
+    fn edit_transaction(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, legolas, gimli) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+        let id = ledger.transactions[0].id;
+
+        // Bilbo actually paid 90, and Gimli was there too
+        let patch = TransactionPatch {
+            contributions: Some(vec![(bilbo.clone(), Amount::from_major(90))]),
+            benefits: Some(vec![
+                (frodo.clone(), Benefit::Even),
+                (legolas.clone(), Benefit::Even),
+                (bilbo.clone(), Benefit::Even),
+                (gimli.clone(), Benefit::Even)
+            ]),
+            description: Some(String::from("Second breakfast")),
+            ..Default::default()
+        };
+        ledger.edit_transaction(id, patch).unwrap();
+
+        assert_eq!(ledger.transactions.len(), 2);
+        assert_eq!(ledger.transactions[0].id, id);
+        assert_eq!(ledger.transactions[0].description, "Second breakfast");
+        assert_eq!(ledger.total_spend, Amount::from_minor(12000));
+        assert_eq!(balance(&ledger, bilbo), Amount::from_minor(6750));
+        assert_eq!(balance(&ledger, gimli), Amount::from_minor(-3450));
+
+        let revisions = ledger.get_revisions(id);
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].transaction.total_spending(), Amount::from_major(60));
+        assert!(ledger.get_revisions(ledger.transactions[1].id).is_empty());
+    }
+
+    #[rstest]
+    fn invalid_edit_rejected(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, gimli) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        let id = ledger.transactions[0].id;
+
+        let too_much = TransactionPatch {
+            benefits: Some(vec![(frodo.clone(), Benefit::Sum(Amount::from_major(70)))]),
+            ..Default::default()
+        };
+        assert!(matches!(ledger.edit_transaction(id, too_much), Err(TransactionError::ExcessBenefits { .. })));
+
+        ledger.deactivate_user(gimli).unwrap();
+        let inactive = TransactionPatch {
+            benefits: Some(vec![(gimli.clone(), Benefit::Even)]),
+            ..Default::default()
+        };
+        assert!(matches!(ledger.edit_transaction(id, inactive), Err(TransactionError::InactiveUser(..))));
+
+        let unknown = TransactionPatch { is_direct: Some(true), ..Default::default() };
+        assert!(matches!(ledger.edit_transaction(99, unknown), Err(TransactionError::UnknownTransactionId(99))));
+
+        assert!(ledger.get_revisions(id).is_empty());
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(40));
+    }
+
+    #[rstest]
+    fn undo_transactions(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", None).unwrap();
+        let (expense, transfer) = (ledger.transactions[0].id, ledger.transactions[1].id);
+
+        ledger.reverse_by_id(transfer).unwrap();
+        assert_eq!(ledger.total_spend, Amount::from_major(60));
+        assert!(ledger.transactions[2].is_direct);
+        assert_eq!(ledger.get_reversal(transfer).unwrap().id, ledger.transactions[2].id);
+
+        ledger.reverse_by_id(expense).unwrap();
+        assert_eq!(ledger.total_spend, Amount::ZERO);
+        assert!(ledger.balances.values().all(|b| b.is_zero()));
+
+        ledger.reapply_all().unwrap();
+        assert_eq!(ledger.total_spend, Amount::ZERO);
+        assert!(ledger.balances.values().all(|b| b.is_zero()));
+    }
+
+    #[rstest]
+    fn undo_with_deactivated_user(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", None).unwrap();
+        ledger.add_transfer(bilbo, frodo, Amount::from_major(20), "", None).unwrap();
+        ledger.deactivate_user(frodo).unwrap();
+
+        ledger.reverse_by_id(ledger.transactions[1].id).unwrap();
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(20));
+
+        // and what the undo left them with can still be settled
+        for settlement in ledger.settlement_plan() {
+            ledger.record_settlement(&settlement, "Settle up", None).unwrap();
+        }
+        assert!(ledger.balances.values().all(|b| b.is_zero()));
+    }
+
+    #[rstest]
+    fn itemized_receipt(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, legolas, gimli) = &user_names;
+        let items = vec![
+            Item { description: String::from("steak"), price: Amount::from_major(45), beneficiaries: vec![gimli.clone()] },
+            Item { description: String::from("wine"), price: Amount::from_major(30), beneficiaries: vec![frodo.clone(), legolas.clone()] }
+        ];
+        let tip = vec![Surcharge::new("Tip", "20%".parse().unwrap())];
+        ledger.add_transaction(Transaction::itemized(bilbo, items, tip, "", None)).unwrap();
+
+        assert_eq!(ledger.total_spend, Amount::from_major(90));
+        assert_eq!(balance(&ledger, gimli), Amount::from_major(-54));
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-18));
+        assert!(ledger.transactions[0].items[0].beneficiaries.iter().all(|user| ledger.get_user(user).is_some()));
+
+        ledger.reverse_by_id(ledger.transactions[0].id).unwrap();
+        assert!(ledger.balances.values().all(|b| b.is_zero()));
+    }
+
+    fn assert_same_state(replayed: &Ledger, ledger: &Ledger) {
+        assert_eq!(replayed.balances, ledger.balances);
+        assert_eq!(replayed.total_spend, ledger.total_spend);
+        assert_eq!(replayed.next_id, ledger.next_id);
+        assert_eq!(replayed.users, ledger.users);
+        let ids = |l: &Ledger| l.transactions.iter().map(|t| (t.id, t.description.clone())).collect::<Vec<_>>();
+        assert_eq!(ids(replayed), ids(ledger));
+        assert_eq!(replayed.revisions.len(), ledger.revisions.len());
+        assert_eq!(replayed.recurring.len(), ledger.recurring.len());
+    }
+
+    #[rstest]
+    fn replay_events(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, gimli) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+        let (first, second) = (ledger.transactions[0].id, ledger.transactions[1].id);
+        ledger.edit_transaction(first, TransactionPatch { description: Some(String::from("Lunch")), ..Default::default() }).unwrap();
+        ledger.reverse_by_id(second).unwrap();
+        ledger.rename_user(bilbo, "Baggins").unwrap();
+        ledger.deactivate_user(gimli).unwrap();
+        let start = NaiveDate::from_ymd(2022, 5, 1);
+        let template = Transaction::new(vec![(frodo, Amount::from_major(9))], vec![(frodo, Benefit::Even)], "", false, None, None);
+        ledger.add_recurring(template, Schedule::Monthly(1), start, None).unwrap();
+        assert!(ledger.materialize_recurring(NaiveDate::from_ymd(2022, 6, 1)).failed.is_empty());
+
+        let replayed = Ledger::from_events(ledger.events().to_vec()).unwrap();
+        assert_same_state(&replayed, &ledger);
+        assert!(replayed.unsaved_events().is_empty());
+        assert_eq!(ledger.unsaved_events().len(), ledger.events().len());
+    }
+
+    #[rstest]
+    fn snapshot_has_events(mut ledger: Ledger, user_names: UserNames4) {
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+        let id = ledger.transactions[0].id;
+        ledger.edit_transaction(id, TransactionPatch { description: Some(String::from("Lunch")), ..Default::default() }).unwrap();
+        ledger.reverse_by_id(id).unwrap();
+
+        let snapshot: Ledger = serde_json::from_str(&serde_json::to_string(&ledger).unwrap()).unwrap();
+        let replayed = Ledger::from_events(snapshot.events().to_vec()).unwrap();
+        assert_same_state(&replayed, &ledger);
+    }
+
+    #[rstest]
+    fn undo_only_once(mut ledger: Ledger, user_names: UserNames4) {
+        add_transaction_bilbo(&mut ledger, &user_names);
+        let id = ledger.transactions[0].id;
+        ledger.reverse_by_id(id).unwrap();
+        let undo_id = ledger.transactions[1].id;
+
+        assert!(matches!(ledger.reverse_by_id(id), Err(TransactionError::AlreadyReversed(..))));
+        assert!(matches!(ledger.reverse_by_id(undo_id), Err(TransactionError::IsReversal(..))));
+        let patch = TransactionPatch { description: Some(String::from("Lunch")), ..Default::default() };
+        assert!(matches!(ledger.edit_transaction(id, patch), Err(TransactionError::AlreadyReversed(..))));
+        assert_eq!(ledger.transactions.len(), 2);
+    }
+
+    #[rstest]
+    fn spending_by_category(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, legolas, _) = &user_names;
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_frodo(&mut ledger, &user_names);
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(2), "", None).unwrap();
+        ledger.transactions[0].category = Some(String::from("food"));
+        ledger.transactions[0].tags = vec![String::from("shire")];
+
+        let food = Some(String::from("food"));
+        let by_category = ledger.spending_by_category().unwrap();
+        assert_eq!(by_category.len(), 2);
+        assert_eq!(by_category[&food], Amount::from_major(60));
+        assert_eq!(by_category[&None], Amount::from_major(30));
+
+        let by_user = ledger.spending_by_user_and_category().unwrap();
+        let bilbo_id = ledger.find_user(bilbo).unwrap().id.clone();
+        let legolas_id = ledger.find_user(legolas).unwrap().id.clone();
+        assert_eq!(by_user[&bilbo_id][&food], Amount::from_major(20));
+        assert!(!by_user[&bilbo_id].contains_key(&None));
+        assert_eq!(by_user[&legolas_id][&None], Amount::from_major(6));
+
+        // an undo takes the spending back off the same category
+        ledger.reverse_by_id(ledger.transactions[0].id).unwrap();
+        assert_eq!(ledger.spending_by_category().unwrap()[&food], Amount::ZERO);
+        assert_eq!(ledger.spending_by_user_and_category().unwrap()[&bilbo_id][&food], Amount::ZERO);
+    }
+
+    #[rstest]
+    fn balances_in_the_past(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        let day = |d| Utc.ymd(2022, 5, d).and_hms(12, 0, 0);
+        // recorded out of order
+        ledger.add_transfer(bilbo, frodo, Amount::from_major(5), "", Some(day(3))).unwrap();
+        ledger.add_transfer(frodo, bilbo, Amount::from_major(20), "", Some(day(1))).unwrap();
+
+        let bilbo_id = ledger.find_user(bilbo).unwrap().id.clone();
+        assert!(ledger.balances_at(day(1) - Duration::seconds(1)).unwrap().values().all(|b| b.is_zero()));
+        assert_eq!(ledger.balances_at(day(1)).unwrap()[&bilbo_id], Amount::from_major(-20));
+        assert_eq!(ledger.balances_at(day(2)).unwrap()[&bilbo_id], Amount::from_major(-20));
+        assert_eq!(ledger.balances_at(day(3)).unwrap(), ledger.get_balances());
+
+        let history = ledger.balance_history().unwrap();
+        assert_eq!(history[&bilbo_id], vec![(day(1), Amount::from_major(-20)), (day(3), Amount::from_major(-15))]);
+        let gimli_id = &ledger.find_user(&user_names.3).unwrap().id;
+        assert!(history[gimli_id].is_empty());
+    }
+
+    #[rstest]
+    fn recurring_rent(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        let date = |m, d| NaiveDate::from_ymd(2022, m, d);
+        let rent = Transaction::new(vec![(bilbo, Amount::from_major(100))],
+            vec![(bilbo, Benefit::Even), (frodo, Benefit::Even)], "Rent", false, None, None);
+        let id = ledger.add_recurring(rent, Schedule::Monthly(1), date(1, 1), Some(date(12, 31))).unwrap();
+
+        assert_eq!(ledger.materialize_recurring(date(3, 15)).created, 3);
+        assert_eq!(ledger.materialize_recurring(date(3, 15)).created, 0);
+        assert_eq!(ledger.transactions.len(), 3);
+        assert_eq!(ledger.transactions[2].datetime, Utc.ymd(2022, 3, 1).and_hms(12, 0, 0));
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-150));
+
+        // April and May are skipped while paused
+        ledger.pause_recurring(id).unwrap();
+        assert_eq!(ledger.materialize_recurring(date(5, 15)).created, 0);
+        ledger.resume_recurring(id, date(5, 16)).unwrap();
+        assert_eq!(ledger.materialize_recurring(date(6, 1)).created, 1);
+
+        // nothing after the end date
+        assert_eq!(ledger.materialize_recurring(NaiveDate::from_ymd(2023, 6, 1)).created, 6);
+        assert_eq!(ledger.transactions.len(), 10);
+        assert!(matches!(ledger.pause_recurring(7), Err(TransactionError::UnknownRecurringId(7))));
+    }
+
+    #[rstest]
+    fn failing_recurring_does_not_block_others(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, gimli) = &user_names;
+        let date = |m, d| NaiveDate::from_ymd(2022, m, d);
+        let rent = Transaction::new(vec![(bilbo, Amount::from_major(100))],
+            vec![(bilbo, Benefit::Even), (frodo, Benefit::Even)], "Rent", false, None, None);
+        let axe = Transaction::new(vec![(gimli, Amount::from_major(10))],
+            vec![(gimli, Benefit::Even)], "Axe polish", false, None, None);
+        ledger.add_recurring(axe, Schedule::Monthly(1), date(1, 1), None).unwrap();
+        let rent_id = ledger.add_recurring(rent, Schedule::Monthly(1), date(1, 1), None).unwrap();
+
+        let res = ledger.remove_user(gimli);
+        assert!(matches!(res, Err(TransactionError::UserHasRecurring(..))));
+        ledger.deactivate_user(gimli).unwrap();
+
+        let run = ledger.materialize_recurring(date(2, 15));
+        assert_eq!(run.created, 2);
+        assert_eq!(run.failed.len(), 1);
+        assert!(matches!(run.failed[0].1, TransactionError::InactiveUser(..)));
+        assert!(ledger.transactions.iter().all(|t| t.description == "Rent"));
+        assert_eq!(ledger.find_recurring(rent_id).unwrap().done_until, Some(date(2, 1)));
+    }
+
+    fn add_transaction_in_usd(ledger: &mut Ledger, user_names: &UserNames4, rate: Option<&str>) -> TransactionResult<()> {
+        let (bilbo, frodo, _, _) = user_names;
+        let mut transaction = Transaction::new(
+            vec![(bilbo, Amount::from_major(20))],
+            vec![(bilbo, Benefit::Even), (frodo, Benefit::Even)],
+            "", false, None, None);
+        transaction.currency = Some("USD".parse().unwrap());
+        transaction.rate = rate.map(|r| r.parse().unwrap());
+        ledger.add_transaction(transaction)
+    }
+
+    #[rstest]
+    fn foreign_currency_converted(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        ledger.set_base_currency("EUR".parse().unwrap()).unwrap();
+
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_in_usd(&mut ledger, &user_names, Some("0.9")).unwrap();
+
+        assert_eq!(ledger.total_spend, Amount::from_major(78));
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(49));
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-29));
+
+        let per_currency = ledger.get_balances_by_currency().unwrap();
+        assert_eq!(per_currency.len(), 2);
+        let bilbo_id = &ledger.find_user(bilbo).unwrap().id;
+        let frodo_id = &ledger.find_user(frodo).unwrap().id;
+        assert_eq!(per_currency[&"EUR".parse().unwrap()][bilbo_id], Amount::from_major(40));
+        assert_eq!(per_currency[&"USD".parse().unwrap()][bilbo_id], Amount::from_major(10));
+        assert_eq!(per_currency[&"USD".parse().unwrap()][frodo_id], Amount::from_major(-10));
+    }
+
+    #[rstest]
+    fn foreign_currency_from_rate_table(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        let usd: Currency = "USD".parse().unwrap();
+        let today = Utc::now().naive_utc().date();
+        ledger.import_rates(vec![
+            RateEntry { date: today.pred(), currency: usd.clone(), rate: "0.5".parse().unwrap() },
+            RateEntry { date: today.succ(), currency: usd.clone(), rate: "2".parse().unwrap() },
+        ]).unwrap();
+
+        add_transaction_in_usd(&mut ledger, &user_names, None).unwrap();
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(5));
+
+        // a rate recorded on the transaction wins over the table
+        add_transaction_in_usd(&mut ledger, &user_names, Some("1")).unwrap();
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-15));
+
+        // importing a rate for the day itself is picked up by existing transactions
+        ledger.import_rates(vec![
+            RateEntry { date: today, currency: usd, rate: "1.5".parse().unwrap() }
+        ]).unwrap();
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(25));
+    }
+
+    #[rstest]
+    fn foreign_currency_needs_rate(mut ledger: Ledger, user_names: UserNames4) {
+        let res = add_transaction_in_usd(&mut ledger, &user_names, None);
+
+        assert!(matches!(res, Err(TransactionError::MissingExchangeRate(..))));
+        assert!(ledger.transactions.is_empty());
+    }
+
+    #[rstest]
+    fn base_currency_is_fixed_once_used(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        let eur: Currency = "EUR".parse().unwrap();
+        ledger.set_base_currency(eur.clone()).unwrap();
+        add_transaction_bilbo(&mut ledger, &user_names);
+        add_transaction_in_usd(&mut ledger, &user_names, Some("0.9")).unwrap();
+
+        let res = ledger.set_base_currency("USD".parse().unwrap());
+        assert!(matches!(res, Err(TransactionError::BaseCurrencyInUse(..))));
+        assert_eq!(ledger.get_base_currency(), &eur);
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(49));
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-29));
+
+        let mut with_rates = Ledger::new(vec!["Bilbo"]);
+        with_rates.import_rates(vec![
+            RateEntry { date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), currency: "USD".parse().unwrap(), rate: "0.9".parse().unwrap() }
+        ]).unwrap();
+        assert!(with_rates.set_base_currency("GBP".parse().unwrap()).is_err());
+    }
+
+    #[rstest]
+    fn failed_currency_change_is_undone(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, _, _, _) = &user_names;
+        let usd: Currency = "USD".parse().unwrap();
+        ledger.set_base_currency(usd.clone()).unwrap();
+        add_transaction_in_usd(&mut ledger, &user_names, None).unwrap();
+        let events = ledger.events().len();
+
+        let res = ledger.commit(Event::BaseCurrencySet { currency: "EUR".parse().unwrap() });
+        assert!(matches!(res, Err(TransactionError::MissingExchangeRate(..))));
+        assert_eq!(ledger.get_base_currency(), &usd);
+        assert_eq!(ledger.events().len(), events);
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(10));
+    }
+
+    #[rstest]
+    fn overflowing_balances_are_refused(mut ledger: Ledger, user_names: UserNames4) {
+        let (bilbo, frodo, _, _) = &user_names;
+        let huge = |rate: &str| {
+            let mut transaction = Transaction::new(vec![(bilbo, Amount::MAX)], vec![(frodo, Benefit::Even)],
+                "", false, None, None);
+            transaction.currency = Some("USD".parse().unwrap());
+            transaction.rate = Some(rate.parse().unwrap());
+            transaction
+        };
+        assert!(matches!(ledger.add_transaction(huge("1000000")), Err(TransactionError::AmountOverflow)));
+
+        ledger.add_transaction(huge("9000")).unwrap();
+        let res = ledger.add_transaction(huge("9000"));
+        assert!(matches!(res, Err(TransactionError::AmountOverflow)));
+        assert_eq!(ledger.transactions.len(), 1);
+        assert_eq!(balance(&ledger, bilbo), Amount::from_minor(9_000_000_000_000_000_000));
+        assert_eq!(ledger.total_spend, Amount::from_minor(9_000_000_000_000_000_000));
     }
 
     #[rstest]
@@ -255,23 +1443,24 @@ mod tests {
         }
 
         // before reapplying all
-        assert_eq!(*ledger.balances.get(bilbo).unwrap(), (repeated_transactions as f32) * 40.0);
-        assert_eq!(*ledger.balances.get(frodo).unwrap(), (repeated_transactions as f32) * -2.0);
-        assert_eq!(*ledger.balances.get(legolas).unwrap(), (repeated_transactions as f32) * -26.0);
-        assert_eq!(*ledger.balances.get(gimli).unwrap(), (repeated_transactions as f32) * -12.0);
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(40) * repeated_transactions as i64);
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-2) * repeated_transactions as i64);
+        assert_eq!(balance(&ledger, legolas), Amount::from_major(-26) * repeated_transactions as i64);
+        assert_eq!(balance(&ledger, gimli), Amount::from_major(-12) * repeated_transactions as i64);
 
         // mess with one of the values
-        *ledger.balances.get_mut(bilbo).unwrap() += 100.0;
+        let bilbo_id = ledger.find_user(bilbo).unwrap().id.clone();
+        *ledger.balances.get_mut(&bilbo_id).unwrap() += Amount::from_major(100);
 
         // one of these should do the consistency check
         add_transaction_bilbo(&mut ledger, &user_names);
         add_transaction_frodo(&mut ledger, &user_names);
 
         // after reapplying all
-        assert_eq!(*ledger.balances.get(bilbo).unwrap(), ((repeated_transactions + 1) as f32) * 40.0);
-        assert_eq!(*ledger.balances.get(frodo).unwrap(), ((repeated_transactions + 1) as f32) * -2.0);
-        assert_eq!(*ledger.balances.get(legolas).unwrap(), ((repeated_transactions + 1) as f32) * -26.0);
-        assert_eq!(*ledger.balances.get(gimli).unwrap(), ((repeated_transactions + 1) as f32) * -12.0);
+        assert_eq!(balance(&ledger, bilbo), Amount::from_major(40) * (repeated_transactions + 1) as i64);
+        assert_eq!(balance(&ledger, frodo), Amount::from_major(-2) * (repeated_transactions + 1) as i64);
+        assert_eq!(balance(&ledger, legolas), Amount::from_major(-26) * (repeated_transactions + 1) as i64);
+        assert_eq!(balance(&ledger, gimli), Amount::from_major(-12) * (repeated_transactions + 1) as i64);
     }
 }
 
@@ -279,7 +1468,7 @@ mod tests {
 #[cfg(test)]
 mod serialise_tests {
     use crate::UserName;
-    use crate::core::{Transaction, Ledger};
+    use crate::core::{Amount, Transaction, Ledger};
     use crate::core::transaction::Benefit;
     use crate::transaction::{AmountPerUser, BenefitPerUser};
 
@@ -302,14 +1491,14 @@ mod serialise_tests {
     fn transaction(users: UserNames4) -> Transaction {
         let (bilbo, frodo, legolas, gimli) = users;
         let contrib: AmountPerUser<&str> = vec![
-            (&bilbo, 32.0),
-            (&frodo, 12.0)
+            (&bilbo, Amount::from_major(32)),
+            (&frodo, Amount::from_major(12))
         ];
 
         let benefit: BenefitPerUser<&str> = vec![
             (&legolas, Benefit::Even),
             (&frodo, Benefit::Even),
-            (&gimli, Benefit::Sum(10.0))
+            (&gimli, Benefit::Sum(Amount::from_major(10)))
         ];
 
         let time = Utc.ymd(2022, 5, 1).and_hms(11, 0, 0);
@@ -323,17 +1512,18 @@ mod serialise_tests {
         json!({
             "id": 1,
             "contributions": [
-                ["Bilbo", 32.0],
-                ["Frodo", 12.0]
+                ["Bilbo", "32.00"],
+                ["Frodo", "12.00"]
             ],
             "benefits": [
                 ["Legolas", "Even"],
                 ["Frodo", "Even"],
-                ["Gimli", {"Sum": 10.0}],
+                ["Gimli", {"Sum": "10.00"}],
             ],
             "is_direct": false,
             "description": "",
-            "datetime": "2022-05-01T11:00:00Z"
+            "datetime": "2022-05-01T11:00:00Z",
+            "remainder": "RoundRobin"
         })
     }
 
@@ -341,10 +1531,10 @@ mod serialise_tests {
     fn ledger_json(transaction_json: serde_json::Value) -> serde_json::Value {
         json!({
             "balances": {
-                "Bilbo": 32.0,
-                "Frodo": -5.0,
-                "Legolas": -17.0,
-                "Gimli": -10.0,
+                "Bilbo": "32.00",
+                "Frodo": "-5.00",
+                "Legolas": "-17.00",
+                "Gimli": "-10.00",
             },
             "users": {
                 "Bilbo": {"name": "Bilbo"},
@@ -353,7 +1543,7 @@ mod serialise_tests {
                 "Gimli": {"name": "Gimli"},
             },
             "next_id": 2,
-            "total_spend": 44.0,
+            "total_spend": "44.00",
             "transactions": [transaction_json]
         })
     }
@@ -364,6 +1554,18 @@ mod serialise_tests {
         assert_eq!(value, transaction_json);
     }
 
+    #[rstest]
+    fn shares_serialize() {
+        let transaction = Transaction::new(vec![("Bilbo", Amount::from_major(3))],
+            vec![("Frodo", Benefit::Shares(2)), ("Gimli", Benefit::Even)], "", false, None, None);
+
+        let value = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(value["benefits"], json!([["Frodo", {"Shares": 2}], ["Gimli", "Even"]]));
+
+        let parsed = serde_json::from_value::<Transaction>(value).unwrap();
+        assert_eq!(parsed.balance_updates().unwrap(), transaction.balance_updates().unwrap());
+    }
+
     #[rstest]
     fn transaction_deserialize(transaction: Transaction, transaction_json: serde_json::Value) {
         let parsed = serde_json::from_value::<Transaction>(transaction_json).unwrap();
@@ -383,26 +1585,73 @@ mod serialise_tests {
     #[rstest]
     fn ledger_serialize(ledger: Ledger, ledger_json: serde_json::Value) {
         let serialised = serde_json::to_value(&ledger).unwrap();
-        assert_eq!(serialised["transactions"], ledger_json["transactions"]);
+        let named: Vec<Transaction> = ledger.get_transactions().iter()
+            .map(|t| ledger.with_display_names(t))
+            .collect();
+        assert_eq!(serde_json::to_value(named).unwrap(), ledger_json["transactions"]);
         assert_eq!(serialised["total_spend"], ledger_json["total_spend"]);
-        for v in ledger_json["balances"].as_object().unwrap() {
-            assert!(serialised["balances"].as_object().unwrap().contains_key(v.0));
+
+        let users = serialised["users"].as_object().unwrap();
+        for (id, user) in users {
+            assert_eq!(user["id"], json!(id));
+            assert!(serialised["balances"].as_object().unwrap().contains_key(id));
+        }
+    }
+
+    #[rstest]
+    fn ledger_roundtrip_keeps_ids(ledger: Ledger) {
+        let value = serde_json::to_value(&ledger).unwrap();
+        let deserialised = serde_json::from_value::<Ledger>(value).unwrap();
+
+        assert_eq!(deserialised.get_balances(), ledger.get_balances());
+        for user in ledger.get_users() {
+            assert_eq!(deserialised.get_user(&user.id), Some(user));
         }
     }
 
     #[rstest]
     fn ledger_deserialize(ledger: Ledger, ledger_json: serde_json::Value) {
+        // the fixture is in the format from before user ids, keyed by name
         let deserialised = serde_json::from_value::<Ledger>(ledger_json).unwrap();
 
-        let users_ledger = ledger.get_users();
+        assert_eq!(deserialised.get_users().len(), ledger.get_users().len());
         for user in deserialised.get_users() {
-            assert!(users_ledger.contains(&user));
+            assert!(!user.id.is_empty());
+            let original = ledger.find_user(&user.name).unwrap();
+            assert_eq!(deserialised.get_balances()[&user.id], ledger.get_balances()[&original.id]);
         }
-
-        let balances_ledger = ledger.get_balances();
-        for (name, balance) in deserialised.get_balances() {
-            assert!(balances_ledger.contains_key(&name));
-            assert_eq!(balances_ledger.get(&name).unwrap(), &balance);
+        for transaction in deserialised.get_transactions() {
+            assert!(transaction.users().all(|id| deserialised.get_user(id).is_some()));
         }
     }
+
+    #[rstest]
+    fn ledger_deserialize_legacy_floats() {
+        let legacy_json = json!({
+            "balances": {"Bilbo": 66.666664, "Frodo": -33.333332, "Legolas": -33.333332},
+            "users": {
+                "Bilbo": {"name": "Bilbo"},
+                "Frodo": {"name": "Frodo"},
+                "Legolas": {"name": "Legolas"},
+            },
+            "next_id": 2,
+            "total_spend": 100.0,
+            "transactions": [{
+                "id": 1,
+                "contributions": [["Bilbo", 100.0]],
+                "benefits": [["Bilbo", "Even"], ["Frodo", "Even"], ["Legolas", "Even"]],
+                "is_direct": false,
+                "description": "",
+                "datetime": "2022-05-01T11:00:00Z"
+            }]
+        });
+
+        let deserialised = serde_json::from_value::<Ledger>(legacy_json).unwrap();
+        let balances = deserialised.get_balances();
+        let id = |name: &str| deserialised.find_user(name).unwrap().id.clone();
+
+        assert_eq!(deserialised.total_spend, Amount::from_major(100));
+        assert_eq!(balances[&id("Bilbo")], Amount::from_minor(6667));
+        assert_eq!(balances[&id("Frodo")], Amount::from_minor(-3333));
+    }
 }
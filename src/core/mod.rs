@@ -1,9 +1,20 @@
+pub mod amount;
+pub mod allocation;
+pub mod currency;
+pub mod rates;
 pub mod user;
 pub mod transaction;
+pub mod receipt;
+pub mod event;
 pub mod ledger;
+pub mod query;
+pub mod recurring;
+pub mod report;
+pub mod settlement;
 pub mod error;
 
-pub use user::{User, UserName, Amount};
+pub use amount::Amount;
+pub use user::{User, UserId, UserName};
 pub use transaction::Transaction;
 pub use ledger::Ledger;
 pub use error::TransactionError;
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use crate::core::amount::Amount;
+use crate::core::ledger::Ledger;
+use crate::core::transaction::{Transaction, TransactionResult};
+use crate::core::user::UserId;
+
+/// A filter over the transactions of a ledger, built up by chaining conditions.
+/// A transaction matches if it meets all of them.
+///
+/// Users can be given by name or by id. They are looked up when the query is run.
+#[derive(Clone)]
+pub struct Query<'a> {
+    ledger: &'a Ledger,
+    involving: Vec<UserId>,
+    paid_by: Vec<UserId>,
+    benefitting: Vec<UserId>,
+    since: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    min: Option<Amount>,
+    max: Option<Amount>,
+    description: Option<Regex>,
+    category: Option<String>,
+    direct: Option<bool>
+}
+
+impl<'a> Query<'a> {
+    pub fn new(ledger: &'a Ledger) -> Query<'a> {
+        return Query {
+            ledger,
+            involving: Vec::new(),
+            paid_by: Vec::new(),
+            benefitting: Vec::new(),
+            since: None,
+            before: None,
+            min: None,
+            max: None,
+            description: None,
+            category: None,
+            direct: None
+        };
+    }
+
+    /// Transactions the user paid for or benefitted from.
+    pub fn involving(mut self, user: &str) -> Self {
+        self.involving.push(user.to_owned());
+        return self;
+    }
+
+    pub fn paid_by(mut self, user: &str) -> Self {
+        self.paid_by.push(user.to_owned());
+        return self;
+    }
+
+    pub fn benefitting(mut self, user: &str) -> Self {
+        self.benefitting.push(user.to_owned());
+        return self;
+    }
+
+    /// Transactions at or after `time`.
+    pub fn since(mut self, time: DateTime<Utc>) -> Self {
+        self.since = Some(time);
+        return self;
+    }
+
+    /// Transactions strictly before `time`.
+    pub fn before(mut self, time: DateTime<Utc>) -> Self {
+        self.before = Some(time);
+        return self;
+    }
+
+    /// Transactions of at least `amount`, in the ledger's base currency.
+    pub fn min_amount(mut self, amount: Amount) -> Self {
+        self.min = Some(amount);
+        return self;
+    }
+
+    /// Transactions of at most `amount`, in the ledger's base currency.
+    pub fn max_amount(mut self, amount: Amount) -> Self {
+        self.max = Some(amount);
+        return self;
+    }
+
+    /// Transactions whose description contains `text`, ignoring case.
+    pub fn description_contains(self, text: &str) -> Self {
+        let pattern = Regex::new(&format!("(?i){}", regex::escape(text)))
+            .expect("escaped text is a valid pattern");
+        return self.description_matches(pattern);
+    }
+
+    pub fn description_matches(mut self, pattern: Regex) -> Self {
+        self.description = Some(pattern);
+        return self;
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.category = Some(category.to_owned());
+        return self;
+    }
+
+    /// Only direct transfers if `direct` is true, or only expenses if it is false.
+    pub fn direct(mut self, direct: bool) -> Self {
+        self.direct = Some(direct);
+        return self;
+    }
+
+    /// The matching transactions, in the order they were recorded.
+    pub fn iter(&self) -> TransactionResult<impl Iterator<Item = &'a Transaction>> {
+        let mut resolved = self.clone();
+        resolved.involving = self.resolve_users(&self.involving)?;
+        resolved.paid_by = self.resolve_users(&self.paid_by)?;
+        resolved.benefitting = self.resolve_users(&self.benefitting)?;
+
+        let mut matching = Vec::new();
+        for transaction in self.ledger.get_transactions() {
+            if resolved.matches(transaction)? {
+                matching.push(transaction);
+            }
+        }
+        return Ok(matching.into_iter());
+    }
+
+    fn resolve_users(&self, users: &[String]) -> TransactionResult<Vec<UserId>> {
+        return users.iter()
+            .map(|user| self.ledger.find_user(user).map(|found| found.id.clone()))
+            .collect();
+    }
+
+    /// Whether the transaction meets all conditions, with users already resolved to ids.
+    fn matches(&self, transaction: &Transaction) -> TransactionResult<bool> {
+        if self.min.is_some() || self.max.is_some() {
+            // converted, so that amounts in different currencies compare by what they are worth
+            let total = self.ledger.exchange_rate(transaction)?.convert(transaction.total_spending())?;
+            if self.min.is_some_and(|min| total < min) || self.max.is_some_and(|max| total > max) {
+                return Ok(false);
+            }
+        }
+        return Ok(self.involving.iter().all(|id| transaction.involves(id))
+            && self.paid_by.iter().all(|id| transaction.is_paid_by(id))
+            && self.benefitting.iter().all(|id| transaction.benefits_user(id))
+            && self.since.is_none_or(|since| transaction.datetime >= since)
+            && self.before.is_none_or(|before| transaction.datetime < before)
+            && self.description.as_ref().is_none_or(|pattern| pattern.is_match(&transaction.description))
+            && self.category.as_ref().is_none_or(|category| transaction.category.as_ref() == Some(category))
+            && self.direct.is_none_or(|direct| transaction.is_direct == direct));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Amount, Ledger, Transaction, TransactionError};
+    use crate::core::transaction::Benefit;
+    use crate::core::query::Query;
+    use chrono::{TimeZone, Utc};
+    use regex::Regex;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn ledger() -> Ledger {
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo", "Sam"]);
+        let day = |d| Some(Utc.ymd(2022, 5, d).and_hms(12, 0, 0));
+        ledger.add_expense(vec![("Bilbo", Amount::from_major(30))],
+            vec![("Bilbo", Benefit::Even), ("Frodo", Benefit::Even)], "Second breakfast", day(1)).unwrap();
+        ledger.add_expense(vec![("Sam", Amount::from_major(12))],
+            vec![("Frodo", Benefit::Even), ("Sam", Benefit::Even)], "Rope", day(2)).unwrap();
+        ledger.add_transfer("Frodo", "Bilbo", Amount::from_major(15), "Paying back breakfast", day(3)).unwrap();
+        return ledger;
+    }
+
+    fn descriptions(query: &Query) -> Vec<String> {
+        return query.iter().unwrap().map(|t| t.description.clone()).collect();
+    }
+
+    #[rstest]
+    fn by_user(ledger: Ledger) {
+        assert_eq!(descriptions(&ledger.query().involving("Sam")), vec!["Rope"]);
+        assert_eq!(descriptions(&ledger.query().paid_by("Frodo")), vec!["Paying back breakfast"]);
+        assert_eq!(descriptions(&ledger.query().benefitting("Frodo").involving("Bilbo")), vec!["Second breakfast"]);
+
+        let res = ledger.query().involving("Gandalf").iter().map(|found| found.count());
+        assert!(matches!(res, Err(TransactionError::UnknownUser(..))));
+    }
+
+    #[rstest]
+    fn by_date_and_amount(ledger: Ledger) {
+        let since = Utc.ymd(2022, 5, 2).and_hms(0, 0, 0);
+        let before = Utc.ymd(2022, 5, 3).and_hms(0, 0, 0);
+        assert_eq!(descriptions(&ledger.query().since(since).before(before)), vec!["Rope"]);
+        assert_eq!(descriptions(&ledger.query().min_amount(Amount::from_major(15))).len(), 2);
+        assert_eq!(descriptions(&ledger.query().max_amount(Amount::from_major(15)).direct(false)), vec!["Rope"]);
+    }
+
+    #[rstest]
+    fn by_amount_in_base_currency() {
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        ledger.set_base_currency("EUR".parse().unwrap()).unwrap();
+        ledger.add_expense(vec![("Bilbo", Amount::from_major(100))],
+            vec![("Frodo", Benefit::Even)], "Pipe-weed", None).unwrap();
+        let mut sushi = Transaction::new(vec![("Frodo", Amount::from_major(100))],
+            vec![("Bilbo", Benefit::Even)], "Sushi", false, None, None);
+        sushi.currency = Some("JPY".parse().unwrap());
+        sushi.rate = Some("0.006".parse().unwrap());
+        ledger.add_transaction(sushi).unwrap();
+
+        assert_eq!(descriptions(&ledger.query().min_amount(Amount::from_major(50))), vec!["Pipe-weed"]);
+        assert_eq!(descriptions(&ledger.query().max_amount(Amount::from_major(1))), vec!["Sushi"]);
+    }
+
+    #[rstest]
+    fn by_description(ledger: Ledger) {
+        assert_eq!(descriptions(&ledger.query().description_contains("BREAKFAST")).len(), 2);
+        let pattern = Regex::new("^Second").unwrap();
+        assert_eq!(descriptions(&ledger.query().description_matches(pattern)), vec!["Second breakfast"]);
+        assert!(descriptions(&ledger.query().category("food")).is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::core::currency::{Currency, Rate};
+
+/// One exchange rate, valid from `date` until the next rate for the same currency.
+/// The rate is the number of units of the ledger's base currency worth one unit of `currency`.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RateEntry {
+    pub date: NaiveDate,
+    pub currency: Currency,
+    pub rate: Rate
+}
+
+impl fmt::Display for RateEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.date, self.currency, self.rate)
+    }
+}
+
+/// Date-stamped exchange rates to a ledger's base currency.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RateTable {
+    rates: BTreeMap<Currency, BTreeMap<NaiveDate, Rate>>
+}
+
+impl RateTable {
+    pub fn is_empty(&self) -> bool {
+        return self.rates.is_empty();
+    }
+
+    /// Adds a rate, replacing any rate for the same currency and date.
+    pub fn insert(&mut self, entry: RateEntry) {
+        self.rates.entry(entry.currency).or_default().insert(entry.date, entry.rate);
+    }
+
+    /// The rate for `currency` in effect at `time`, which is the
+    /// latest one dated on or before that day.
+    pub fn rate_at(&self, currency: &Currency, time: DateTime<Utc>) -> Option<Rate> {
+        return self.rates.get(currency)?
+            .range(..=time.naive_utc().date())
+            .next_back()
+            .map(|(_, rate)| *rate);
+    }
+
+    /// All rates, ordered by currency and then by date.
+    pub fn entries(&self) -> Vec<RateEntry> {
+        return self.rates.iter()
+            .flat_map(|(currency, by_date)| by_date.iter().map(|(date, rate)| {
+                RateEntry { date: *date, currency: currency.clone(), rate: *rate }
+            }))
+            .collect();
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseRatesError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseRatesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseRatesError {}
+
+/// Reads rates from CSV text with the columns `date,currency,rate`, e.g. `2022-05-01,USD,0.95`.
+/// A header line starting with `date`, blank lines and lines starting with `#` are skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<RateEntry>, ParseRatesError> {
+    let mut entries = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (idx == 0 && line.starts_with("date")) {
+            continue;
+        }
+        let error = |message: String| ParseRatesError { line: idx + 1, message };
+
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if fields.len() != 3 {
+            return Err(error(format!("expected 3 fields, found {}", fields.len())));
+        }
+        let date = fields[0].parse::<NaiveDate>().map_err(|err| error(err.to_string()))?;
+        let currency = fields[1].parse::<Currency>().map_err(|err| error(err.to_string()))?;
+        let rate = fields[2].parse::<Rate>().map_err(|err| error(err.to_string()))?;
+        entries.push(RateEntry { date, currency, rate });
+    }
+    return Ok(entries);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::currency::Currency;
+    use crate::core::rates::{parse_csv, RateTable};
+    use chrono::{TimeZone, Utc};
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn table() -> RateTable {
+        let csv = "date,currency,rate\n\
+            2022-05-01,USD,0.95\n\
+            # comment\n\
+            \n\
+            2022-06-01, usd, 0.97\n\
+            2022-05-15,GBP,1.18\n";
+        let mut table = RateTable::default();
+        for entry in parse_csv(csv).unwrap() {
+            table.insert(entry);
+        }
+        return table;
+    }
+
+    #[rstest]
+    fn rate_in_effect(table: RateTable) {
+        let usd: Currency = "USD".parse().unwrap();
+
+        assert_eq!(table.rate_at(&usd, Utc.ymd(2022, 4, 30).and_hms(23, 0, 0)), None);
+        assert_eq!(table.rate_at(&usd, Utc.ymd(2022, 5, 1).and_hms(0, 0, 0)), Some("0.95".parse().unwrap()));
+        assert_eq!(table.rate_at(&usd, Utc.ymd(2022, 5, 31).and_hms(12, 0, 0)), Some("0.95".parse().unwrap()));
+        assert_eq!(table.rate_at(&usd, Utc.ymd(2023, 1, 1).and_hms(12, 0, 0)), Some("0.97".parse().unwrap()));
+        assert_eq!(table.rate_at(&"EUR".parse().unwrap(), Utc.ymd(2023, 1, 1).and_hms(12, 0, 0)), None);
+    }
+
+    #[rstest]
+    fn entries_ordered(table: RateTable) {
+        let listed: Vec<String> = table.entries().iter().map(|e| e.to_string()).collect();
+        assert_eq!(listed, vec![
+            "2022-05-15 GBP 1.18",
+            "2022-05-01 USD 0.95",
+            "2022-06-01 USD 0.97"
+        ]);
+    }
+
+    #[rstest]
+    fn bad_csv_line() {
+        let err = parse_csv("2022-05-01,USD,0.95\n2022-05-02,USD\n").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = parse_csv("2022-13-01,USD,0.95\n").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+
+    #[rstest]
+    fn serde_roundtrip(table: RateTable) {
+        let value = serde_json::to_value(&table).unwrap();
+        assert_eq!(value["USD"]["2022-06-01"], "0.97");
+
+        let parsed = serde_json::from_value::<RateTable>(value).unwrap();
+        assert_eq!(parsed.entries(), table.entries());
+    }
+}
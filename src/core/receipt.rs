@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::core::amount::{self, Amount, ParseAmountError};
+use crate::core::allocation::{self, RemainderPolicy};
+use crate::core::transaction::AmountPerUser;
+use crate::core::user::UserId;
+
+/// One line of an itemized receipt, shared evenly between its beneficiaries.
+/// A user listed more than once takes that many shares of the item.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Item {
+    pub description: String,
+    pub price: Amount,
+    pub beneficiaries: Vec<UserId>
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.description, self.price, self.beneficiaries.join(", "))
+    }
+}
+
+/// An extra charge on an expense, such as tax or a tip. A negative value is a discount.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Surcharge {
+    pub name: String,
+    pub value: SurchargeValue
+}
+
+impl Surcharge {
+    pub fn new(name: &str, value: SurchargeValue) -> Surcharge {
+        return Surcharge { name: name.to_owned(), value };
+    }
+
+    /// The amount charged on top of `subtotal`, rounded half away from zero.
+    pub fn amount(&self, subtotal: Amount) -> Amount {
+        match self.value {
+            SurchargeValue::Fixed(amount) => amount,
+            SurchargeValue::Percent(hundredths) => {
+                let scaled = subtotal.minor() as i128 * hundredths as i128;
+                let units = SurchargeValue::HUNDREDTHS_PER_WHOLE as i128;
+                let rounded = (scaled.abs() + units / 2) / units * scaled.signum();
+                Amount::from_minor(rounded as i64)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Surcharge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.value)
+    }
+}
+
+/// How big a surcharge is, written as e.g. `15%`, `8.25%` or `4.50`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SurchargeValue {
+    /// A percentage of the subtotal, in hundredths of a percent (`850` is 8.5%)
+    Percent(i64),
+    /// A fixed amount
+    Fixed(Amount)
+}
+
+impl SurchargeValue {
+    /// Hundredths of a percent making up the whole subtotal
+    const HUNDREDTHS_PER_WHOLE: i64 = 10_000;
+}
+
+impl std::ops::Neg for SurchargeValue {
+    type Output = SurchargeValue;
+    fn neg(self) -> SurchargeValue {
+        match self {
+            SurchargeValue::Percent(hundredths) => SurchargeValue::Percent(-hundredths),
+            SurchargeValue::Fixed(amount) => SurchargeValue::Fixed(-amount)
+        }
+    }
+}
+
+impl fmt::Display for SurchargeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SurchargeValue::Fixed(amount) => write!(f, "{}", amount),
+            SurchargeValue::Percent(hundredths) => {
+                let sign = if *hundredths < 0 { "-" } else { "" };
+                let (whole, frac) = (hundredths.abs() / 100, hundredths.abs() % 100);
+                let frac = format!("{:02}", frac);
+                let frac = frac.trim_end_matches('0');
+                if frac.is_empty() {
+                    write!(f, "{}{}%", sign, whole)
+                } else {
+                    write!(f, "{}{}.{}%", sign, whole, frac)
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for SurchargeValue {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.strip_suffix('%') {
+            Some(pct) => amount::parse_fixed_point(pct, 2).map(SurchargeValue::Percent),
+            None => s.parse().map(SurchargeValue::Fixed)
+        };
+    }
+}
+
+impl Serialize for SurchargeValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SurchargeValue {
+    fn deserialize<D>(deserializer: D) -> Result<SurchargeValue, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// The sum of all surcharges on top of `subtotal`.
+pub fn surcharge_total(surcharges: &[Surcharge], subtotal: Amount) -> Amount {
+    return surcharges.iter().map(|surcharge| surcharge.amount(subtotal)).sum();
+}
+
+/// The subtotal which comes to `total` once the surcharges are added on top.
+/// If rounding means that no subtotal comes to it exactly, the nearest is used.
+pub fn subtotal_before(surcharges: &[Surcharge], total: Amount) -> Amount {
+    if surcharges.is_empty() {
+        return total;
+    }
+    let mut fixed = Amount::ZERO;
+    let mut hundredths = 0;
+    for surcharge in surcharges {
+        match surcharge.value {
+            SurchargeValue::Fixed(amount) => fixed += amount,
+            SurchargeValue::Percent(value) => hundredths += value
+        }
+    }
+    let whole = SurchargeValue::HUNDREDTHS_PER_WHOLE;
+    if whole + hundredths <= 0 {
+        return total - fixed;
+    }
+
+    // each percentage is rounded separately, so the estimate can be off by a few minor units
+    let estimate = ((total - fixed).minor() as i128 * whole as i128 / (whole + hundredths) as i128) as i64;
+    let reach = surcharges.len() as i64 + 1;
+    return (estimate - reach..=estimate + reach)
+        .map(Amount::from_minor)
+        .find(|subtotal| *subtotal + surcharge_total(surcharges, *subtotal) == total)
+        .unwrap_or(Amount::from_minor(estimate));
+}
+
+/// Splits each item between its beneficiaries. Returns the amount per user, in
+/// the order they first appear, and the part which could not be allocated.
+pub fn item_amounts(items: &[Item], policy: RemainderPolicy) -> (AmountPerUser<UserId>, Amount) {
+    let mut amounts: AmountPerUser<UserId> = Vec::new();
+    let mut leftover = Amount::ZERO;
+    for item in items {
+        let weights = vec![1; item.beneficiaries.len()];
+        let (shares, item_leftover) = allocation::split(item.price, &weights, policy);
+        for (user, share) in item.beneficiaries.iter().zip(shares) {
+            add_to(&mut amounts, user, share);
+        }
+        leftover += item_leftover;
+    }
+    return (amounts, leftover);
+}
+
+/// Spreads `surcharge` over the users in proportion to the amounts they already have.
+/// Returns the part which could not be allocated.
+pub fn spread_proportionally(amounts: &mut AmountPerUser<UserId>, surcharge: Amount, policy: RemainderPolicy) -> Amount {
+    let weights: Vec<u64> = amounts.iter()
+        .map(|(_, amount)| amount.minor().max(0) as u64)
+        .collect();
+    let (shares, leftover) = allocation::split(surcharge, &weights, policy);
+    for ((_, amount), share) in amounts.iter_mut().zip(shares) {
+        *amount += share;
+    }
+    return leftover;
+}
+
+fn add_to(amounts: &mut AmountPerUser<UserId>, user: &UserId, amount: Amount) {
+    match amounts.iter_mut().find(|(u, _)| u == user) {
+        Some(entry) => entry.1 += amount,
+        None => amounts.push((user.clone(), amount))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Amount;
+    use crate::core::allocation::RemainderPolicy;
+    use crate::core::receipt::{item_amounts, spread_proportionally, subtotal_before, surcharge_total, Item, Surcharge, SurchargeValue};
+    use rstest::rstest;
+
+    fn item(price: i64, beneficiaries: &[&str]) -> Item {
+        Item {
+            description: String::new(),
+            price: Amount::from_minor(price),
+            beneficiaries: beneficiaries.iter().map(|user| user.to_string()).collect()
+        }
+    }
+
+    #[rstest]
+    #[case("15%", SurchargeValue::Percent(1500))]
+    #[case("8.25%", SurchargeValue::Percent(825))]
+    #[case("-10%", SurchargeValue::Percent(-1000))]
+    #[case("4.50", SurchargeValue::Fixed(Amount::from_minor(450)))]
+    fn parse_surcharge(#[case] input: &str, #[case] value: SurchargeValue) {
+        assert_eq!(input.parse::<SurchargeValue>().unwrap(), value);
+        assert_eq!(value.to_string(), input);
+    }
+
+    #[rstest]
+    fn surcharge_amount() {
+        let tax = Surcharge::new("tax", "8.5%".parse().unwrap());
+        assert_eq!(tax.amount(Amount::from_major(10)), Amount::from_minor(85));
+        // 2.975 rounds up
+        assert_eq!(tax.amount(Amount::from_minor(3500)), Amount::from_minor(298));
+        let discount = Surcharge::new("discount", -"5".parse::<SurchargeValue>().unwrap());
+        assert_eq!(discount.amount(Amount::from_major(10)), Amount::from_major(-5));
+    }
+
+    #[rstest]
+    #[case("15%", 10000)]
+    #[case("8.25%", 333)]
+    #[case("-10%", 4999)]
+    #[case("2.50", 1234)]
+    fn subtotal_from_total(#[case] value: &str, #[case] subtotal: i64) {
+        let surcharges = vec![Surcharge::new("tip", value.parse().unwrap()), Surcharge::new("tax", "8.5%".parse().unwrap())];
+        let subtotal = Amount::from_minor(subtotal);
+        let total = subtotal + surcharge_total(&surcharges, subtotal);
+        assert_eq!(subtotal_before(&surcharges, total), subtotal);
+    }
+
+    #[rstest]
+    fn split_items() {
+        let items = vec![item(4500, &["Ben"]), item(3001, &["Alex", "Ben"])];
+        let (amounts, leftover) = item_amounts(&items, RemainderPolicy::RoundRobin);
+
+        assert_eq!(amounts, vec![("Ben".to_string(), Amount::from_minor(6000)), ("Alex".to_string(), Amount::from_minor(1501))]);
+        assert_eq!(leftover, Amount::ZERO);
+    }
+
+    #[rstest]
+    fn spread_surcharge() {
+        let mut amounts = vec![("Ben".to_string(), Amount::from_major(30)), ("Alex".to_string(), Amount::from_major(10))];
+        let leftover = spread_proportionally(&mut amounts, Amount::from_major(6), RemainderPolicy::RoundRobin);
+
+        assert_eq!(amounts[0].1, Amount::from_minor(3450));
+        assert_eq!(amounts[1].1, Amount::from_minor(1150));
+        assert_eq!(leftover, Amount::ZERO);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{Serialize, Deserialize};
+
+use crate::core::transaction::Transaction;
+use crate::core::error::TransactionError;
+
+/// How often a recurring transaction happens.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Schedule {
+    /// Every week on the given day
+    Weekly(Weekday),
+    /// Every month on the given day. In months which are
+    /// too short, the last day of the month is used instead.
+    Monthly(u32)
+}
+
+impl Schedule {
+    /// All days the schedule falls on from `from` to `to`, both included.
+    pub fn dates_between(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        match self {
+            Schedule::Weekly(weekday) => {
+                let offset = (7 + weekday.num_days_from_monday() as i64
+                    - from.weekday().num_days_from_monday() as i64) % 7;
+                let mut date = from + Duration::days(offset);
+                while date <= to {
+                    dates.push(date);
+                    date += Duration::weeks(1);
+                }
+            },
+            Schedule::Monthly(day) => {
+                let (mut year, mut month) = (from.year(), from.month());
+                loop {
+                    let date = day_of_month(year, month, *day);
+                    if date > to {
+                        break;
+                    }
+                    if date >= from {
+                        dates.push(date);
+                    }
+                    (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                }
+            }
+        }
+        return dates;
+    }
+}
+
+/// The given day of the month, or the last day if the month is shorter.
+fn day_of_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    return (1..=day).rev()
+        .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .unwrap_or_else(|| NaiveDate::from_ymd(year, month, 1));
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Weekly(weekday) => write!(f, "weekly:{}", weekday.to_string().to_lowercase()),
+            Schedule::Monthly(day) => write!(f, "monthly:{}", day)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseScheduleError(String);
+
+impl fmt::Display for ParseScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a schedule: {} (expected e.g. monthly:1 or weekly:mon)", self.0)
+    }
+}
+
+impl std::error::Error for ParseScheduleError {}
+
+impl FromStr for Schedule {
+    type Err = ParseScheduleError;
+
+    /// Parses `monthly:DAY`, with a day of the month from 1 to 31,
+    /// or `weekly:WEEKDAY`, with a weekday such as `mon` or `friday`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseScheduleError(s.to_owned());
+        let (kind, when) = s.split_once(':').ok_or_else(error)?;
+        match kind {
+            "monthly" => match when.parse::<u32>() {
+                Ok(day) if (1..=31).contains(&day) => Ok(Schedule::Monthly(day)),
+                _ => Err(error())
+            },
+            "weekly" => when.parse::<Weekday>().map(Schedule::Weekly).map_err(|_| error()),
+            _ => Err(error())
+        }
+    }
+}
+
+/// The outcome of running the recurring transactions which are due.
+#[derive(Debug, Default)]
+pub struct RecurringRun {
+    /// Number of transactions created
+    pub created: usize,
+    /// Recurring transactions which could not be run, by id, and why.
+    /// Their repetitions are left to be created by a later run.
+    pub failed: Vec<(usize, TransactionError)>
+}
+
+/// A transaction which is repeated on a schedule, such as rent.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Recurring {
+    pub id: usize,
+    /// The transaction to repeat. Its id and time are replaced on each repetition.
+    pub template: Transaction,
+    pub schedule: Schedule,
+    pub start: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<NaiveDate>,
+    #[serde(default)]
+    pub paused: bool,
+    /// The last day up to which transactions have been created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done_until: Option<NaiveDate>
+}
+
+impl Recurring {
+    /// Days on which a transaction is due and has not been created yet, up to `until`.
+    pub fn due_dates(&self, until: NaiveDate) -> Vec<NaiveDate> {
+        if self.paused {
+            return Vec::new();
+        }
+        let from = match self.done_until {
+            Some(done) => std::cmp::max(self.start, done.succ()),
+            None => self.start
+        };
+        let to = match self.end {
+            Some(end) => std::cmp::min(end, until),
+            None => until
+        };
+        if from > to {
+            return Vec::new();
+        }
+        return self.schedule.dates_between(from, to);
+    }
+}
+
+impl fmt::Display for Recurring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{} from {}", self.id, self.schedule, self.start)?;
+        if let Some(end) = self.end {
+            write!(f, " to {}", end)?;
+        }
+        if self.paused {
+            write!(f, " (paused)")?;
+        }
+        write!(f, ": {} {}", self.template.total_spending(), self.template.description)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::recurring::Schedule;
+    use chrono::{NaiveDate, Weekday};
+    use rstest::rstest;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(y, m, d)
+    }
+
+    #[rstest]
+    fn parse_schedule() {
+        assert_eq!("monthly:1".parse::<Schedule>().unwrap(), Schedule::Monthly(1));
+        assert_eq!("weekly:fri".parse::<Schedule>().unwrap(), Schedule::Weekly(Weekday::Fri));
+        assert_eq!(Schedule::Monthly(15).to_string().parse::<Schedule>().unwrap(), Schedule::Monthly(15));
+        assert_eq!(Schedule::Weekly(Weekday::Fri).to_string(), "weekly:fri");
+        assert!("monthly:32".parse::<Schedule>().is_err());
+        assert!("daily:1".parse::<Schedule>().is_err());
+    }
+
+    #[rstest]
+    fn monthly_dates() {
+        let dates = Schedule::Monthly(31).dates_between(date(2022, 1, 15), date(2022, 4, 30));
+        assert_eq!(dates, vec![date(2022, 1, 31), date(2022, 2, 28), date(2022, 3, 31), date(2022, 4, 30)]);
+
+        let dates = Schedule::Monthly(1).dates_between(date(2022, 12, 1), date(2023, 1, 1));
+        assert_eq!(dates, vec![date(2022, 12, 1), date(2023, 1, 1)]);
+    }
+
+    #[rstest]
+    fn weekly_dates() {
+        // 2022-05-02 is a Monday
+        let dates = Schedule::Weekly(Weekday::Wed).dates_between(date(2022, 5, 2), date(2022, 5, 18));
+        assert_eq!(dates, vec![date(2022, 5, 4), date(2022, 5, 11), date(2022, 5, 18)]);
+    }
+}
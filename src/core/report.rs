@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::core::amount::Amount;
+use crate::core::ledger::Ledger;
+use crate::core::transaction::TransactionResult;
+use crate::core::user::UserId;
+
+/// A length of time that spending is grouped by. Weeks start on Monday.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Period {
+    Week,
+    Month,
+    Year
+}
+
+impl Period {
+    /// The first day of the period which `time` falls in, by UTC date.
+    pub fn start_of(&self, time: DateTime<Utc>) -> NaiveDate {
+        let date = time.naive_utc().date();
+        return match self {
+            Period::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+            Period::Year => NaiveDate::from_ymd(date.year(), 1, 1)
+        };
+    }
+
+    /// A short name for the period starting on `start`, e.g. `2022-W18`, `2022-05` or `2022`.
+    pub fn label(&self, start: NaiveDate) -> String {
+        return match self {
+            Period::Week => {
+                let week = start.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            },
+            Period::Month => start.format("%Y-%m").to_string(),
+            Period::Year => start.format("%Y").to_string()
+        };
+    }
+}
+
+/// How much a user paid towards expenses, and how much of them they consumed.
+/// Direct transfers are not counted as either.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Spending {
+    pub paid: Amount,
+    pub consumed: Amount
+}
+
+impl Spending {
+    pub fn net(&self) -> Amount {
+        return self.paid - self.consumed;
+    }
+}
+
+impl fmt::Display for Spending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "paid {}, consumed {}, net {}", self.paid, self.consumed, self.net())
+    }
+}
+
+/// Spending per user, for each period identified by the day it starts.
+pub type Report = BTreeMap<NaiveDate, HashMap<UserId, Spending>>;
+
+/// Works out what each user paid and consumed in every period with any expenses,
+/// in the ledger's base currency. Undos count against the period they were made in.
+pub fn spending_report(ledger: &Ledger, period: Period) -> TransactionResult<Report> {
+    let mut report = Report::new();
+    for transaction in ledger.get_transactions() {
+        if transaction.is_direct {
+            continue;
+        }
+        let rate = ledger.exchange_rate(transaction)?;
+        let per_user = report.entry(period.start_of(transaction.datetime)).or_default();
+        for (user, amount) in transaction.converted_payments(rate)? {
+            per_user.entry(user).or_default().paid += amount;
+        }
+        for (user, amount) in transaction.converted_consumption(rate)? {
+            per_user.entry(user).or_default().consumed += amount;
+        }
+    }
+    return Ok(report);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Amount, Ledger};
+    use crate::core::report::{spending_report, Period, Spending};
+    use crate::core::transaction::Benefit;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Period::Week, NaiveDate::from_ymd(2022, 5, 2), "2022-W18")]
+    #[case(Period::Month, NaiveDate::from_ymd(2022, 5, 1), "2022-05")]
+    #[case(Period::Year, NaiveDate::from_ymd(2022, 1, 1), "2022")]
+    fn period_start(#[case] period: Period, #[case] start: NaiveDate, #[case] label: &str) {
+        // a Sunday
+        let time = Utc.ymd(2022, 5, 8).and_hms(23, 0, 0);
+        assert_eq!(period.start_of(time), start);
+        assert_eq!(period.label(start), label);
+    }
+
+    #[rstest]
+    fn monthly_report() {
+        let mut ledger = Ledger::new(vec!["Bilbo", "Frodo"]);
+        let may = Some(Utc.ymd(2022, 5, 10).and_hms(12, 0, 0));
+        let june = Some(Utc.ymd(2022, 6, 10).and_hms(12, 0, 0));
+        ledger.add_expense(vec![("Bilbo", Amount::from_major(30))],
+            vec![("Bilbo", Benefit::Even), ("Frodo", Benefit::Even)], "", may).unwrap();
+        ledger.add_expense(vec![("Frodo", Amount::from_major(8))],
+            vec![("Frodo", Benefit::Even)], "", june).unwrap();
+        ledger.add_transfer("Frodo", "Bilbo", Amount::from_major(15), "", june).unwrap();
+
+        let report = spending_report(&ledger, Period::Month).unwrap();
+        let bilbo = &ledger.find_user("Bilbo").unwrap().id;
+        let frodo = &ledger.find_user("Frodo").unwrap().id;
+        let may = &report[&NaiveDate::from_ymd(2022, 5, 1)];
+        let june = &report[&NaiveDate::from_ymd(2022, 6, 1)];
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(may[bilbo], Spending { paid: Amount::from_major(30), consumed: Amount::from_major(15) });
+        assert_eq!(may[frodo].net(), Amount::from_major(-15));
+        assert_eq!(june[frodo], Spending { paid: Amount::from_major(8), consumed: Amount::from_major(8) });
+        assert!(!june.contains_key(bilbo));
+    }
+}
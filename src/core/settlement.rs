@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::core::amount::Amount;
+use crate::core::user::UserId;
+use crate::core::transaction::UserAmountMap;
+
+/// A single direct payment which moves the group towards zero balances.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Settlement {
+    pub from: UserId,
+    pub to: UserId,
+    pub amount: Amount
+}
+
+impl fmt::Display for Settlement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}: {}", self.from, self.to, self.amount)
+    }
+}
+
+/// Works out a list of transfers which brings every balance to zero.
+///
+/// Debts that exactly cancel out are paired first, then the largest debtor
+/// repeatedly pays the largest creditor. This needs at most one transfer fewer
+/// than the number of users with a non-zero balance, which is near-minimal in
+/// practice. The result only depends on the balances, not on the map's ordering.
+pub fn plan(balances: &UserAmountMap) -> Vec<Settlement> {
+    let mut debtors: Vec<(UserId, Amount)> = Vec::new();
+    let mut creditors: Vec<(UserId, Amount)> = Vec::new();
+    for (user, balance) in balances {
+        if balance.is_negative() {
+            debtors.push((user.clone(), -*balance));
+        } else if balance.is_positive() {
+            creditors.push((user.clone(), *balance));
+        }
+    }
+    let by_amount_then_id = |a: &(UserId, Amount), b: &(UserId, Amount)| {
+        b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))
+    };
+    debtors.sort_by(by_amount_then_id);
+    creditors.sort_by(by_amount_then_id);
+
+    let mut settlements = Vec::new();
+
+    // exact matches settle two people with a single transfer
+    debtors.retain(|(debtor, owed)| {
+        match creditors.iter().position(|(_, due)| due == owed) {
+            Some(idx) => {
+                let (creditor, due) = creditors.remove(idx);
+                settlements.push(Settlement { from: debtor.clone(), to: creditor, amount: due });
+                false
+            },
+            None => true
+        }
+    });
+
+    while !debtors.is_empty() && !creditors.is_empty() {
+        let (debtor, owed) = debtors[0].clone();
+        let (creditor, due) = creditors[0].clone();
+        let amount = std::cmp::min(owed, due);
+        settlements.push(Settlement { from: debtor, to: creditor, amount });
+
+        debtors[0].1 -= amount;
+        creditors[0].1 -= amount;
+        debtors.retain(|(_, left)| !left.is_zero());
+        creditors.retain(|(_, left)| !left.is_zero());
+        debtors.sort_by(by_amount_then_id);
+        creditors.sort_by(by_amount_then_id);
+    }
+
+    return settlements;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Amount;
+    use crate::core::settlement::{plan, Settlement};
+    use crate::core::transaction::UserAmountMap;
+    use rstest::rstest;
+
+    fn balances(values: &[(&str, i64)]) -> UserAmountMap {
+        values.iter().map(|(user, amt)| (user.to_string(), Amount::from_major(*amt))).collect()
+    }
+
+    fn settle(balances: &mut UserAmountMap, settlements: &[Settlement]) {
+        for s in settlements {
+            *balances.get_mut(&s.from).unwrap() += s.amount;
+            *balances.get_mut(&s.to).unwrap() -= s.amount;
+        }
+    }
+
+    #[rstest]
+    fn zero_balances_need_nothing() {
+        assert!(plan(&balances(&[("Bilbo", 0), ("Frodo", 0)])).is_empty());
+    }
+
+    #[rstest]
+    fn exact_matches_paired() {
+        let mut bal = balances(&[("Bilbo", 10), ("Frodo", 25), ("Legolas", -10), ("Gimli", -25)]);
+        let settlements = plan(&bal);
+
+        assert_eq!(settlements.len(), 2);
+        assert!(settlements.contains(&Settlement {
+            from: "Legolas".to_string(), to: "Bilbo".to_string(), amount: Amount::from_major(10) }));
+        settle(&mut bal, &settlements);
+        assert!(bal.values().all(|b| b.is_zero()));
+    }
+
+    #[rstest]
+    fn greedy_zeroes_all_balances() {
+        let mut bal = balances(&[("Bilbo", 40), ("Frodo", -2), ("Legolas", -26), ("Gimli", -12)]);
+        let settlements = plan(&bal);
+
+        assert_eq!(settlements.len(), 3);
+        assert!(settlements.iter().all(|s| s.to == "Bilbo"));
+        settle(&mut bal, &settlements);
+        assert!(bal.values().all(|b| b.is_zero()));
+    }
+
+    #[rstest]
+    fn can_print() {
+        let settlement = Settlement {
+            from: "Frodo".to_string(), to: "Bilbo".to_string(), amount: Amount::from_minor(1250) };
+        assert_eq!(settlement.to_string(), "Frodo -> Bilbo: 12.50");
+    }
+}
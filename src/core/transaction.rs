@@ -1,25 +1,57 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Serialize, Deserialize};
 use colored::Colorize;
 use chrono::{DateTime, offset::Local, Utc};
 
-use crate::core::user::{UserName, Amount};
+use crate::core::amount::Amount;
+use crate::core::allocation::{self, RemainderPolicy};
+use crate::core::currency::{Currency, Rate};
+use crate::core::receipt::{self, Item, Surcharge};
+use crate::core::user::UserId;
 use crate::core::error::TransactionError;
 
-pub type UserAmountMap = HashMap<UserName, Amount>;
+pub type UserAmountMap = HashMap<UserId, Amount>;
+/// Amounts per category. Transactions without a category are under `None`.
+pub type CategoryAmountMap = BTreeMap<Option<String>, Amount>;
 
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Benefit {
+    /// A fixed amount
     Sum(Amount),
-    Even
+    /// One share of what is left after the fixed amounts
+    Even,
+    /// The given number of shares of what is left after the fixed amounts
+    Shares(u32),
+    /// A whole percentage of the total spent
+    Percent(u32)
+}
+
+impl Benefit {
+    /// Number of shares of the remainder this benefit takes.
+    fn shares(&self) -> u64 {
+        match self {
+            Self::Sum(_) | Self::Percent(_) => 0,
+            Self::Even => 1,
+            Self::Shares(weight) => *weight as u64
+        }
+    }
+
+    fn percent(&self) -> u32 {
+        match self {
+            Self::Percent(pct) => *pct,
+            _ => 0
+        }
+    }
 }
 
 impl std::fmt::Display for Benefit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let disp = match self {
             Self::Sum(amt) => amt.to_string(),
-            Self::Even => "Even".to_string()
+            Self::Even => "Even".to_string(),
+            Self::Shares(weight) => format!("{}x", weight),
+            Self::Percent(pct) => format!("{}%", pct)
         };
         write!(f, "{}", disp)
     }
@@ -38,7 +70,7 @@ trait ToOwnedUsers {
 }
 
 impl<T: Copy> ToOwnedUsers for Vec<(&str, T)> {
-    type WithOwnedUsers = Vec<(UserName, T)>;
+    type WithOwnedUsers = Vec<(UserId, T)>;
 
     fn to_owned_users(&self) -> Self::WithOwnedUsers {
         self.iter().map(|pair| (pair.0.to_owned(), pair.1)).collect()
@@ -46,18 +78,117 @@ impl<T: Copy> ToOwnedUsers for Vec<(&str, T)> {
 }
 
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: usize,
     #[serde(with = "datetime_serialization")]
     pub datetime: DateTime<Utc>,
-    contributions: AmountPerUser<UserName>,
-    benefits: BenefitPerUser<UserName>,
+    contributions: AmountPerUser<UserId>,
+    benefits: BenefitPerUser<UserId>,
     pub is_direct: bool,
-    pub description: String
+    pub description: String,
+    /// How to share out minor units that cannot be split evenly
+    #[serde(default)]
+    pub remainder: RemainderPolicy,
+    /// Currency of all the amounts. `None` means the ledger's base currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Units of the ledger's base currency worth one unit of `currency`,
+    /// as recorded when the transaction was entered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    /// Id of the transaction this one undoes, if it is an undo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<usize>,
+    /// What the money was spent on, e.g. "food"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Lines of an itemized receipt. When there are any,
+    /// they decide who benefitted instead of `benefits`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<Item>,
+    /// Tax, tips, discounts and the like, shared in proportion to what each user consumed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub surcharges: Vec<Surcharge>,
+    /// What an expense with surcharges but no items came to before them, as it
+    /// can't always be worked back from the total, e.g. after a 100% discount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) subtotal: Option<Amount>
+}
+
+/// Changes to an existing transaction. Fields left as `None` are kept as they were.
+#[derive(Clone, Default, Debug)]
+pub struct TransactionPatch {
+    pub contributions: Option<AmountPerUser<UserId>>,
+    pub benefits: Option<BenefitPerUser<UserId>>,
+    pub description: Option<String>,
+    pub datetime: Option<DateTime<Utc>>,
+    pub is_direct: Option<bool>,
+    pub items: Option<Vec<Item>>,
+    pub surcharges: Option<Vec<Surcharge>>
 }
 
-mod datetime_serialization {
+impl TransactionPatch {
+    pub fn is_empty(&self) -> bool {
+        return self.contributions.is_none() && self.benefits.is_none() && self.description.is_none()
+            && self.datetime.is_none() && self.is_direct.is_none() && self.items.is_none()
+            && self.surcharges.is_none();
+    }
+
+    /// A copy of the transaction with the changes made. Contributions include
+    /// the surcharges, so they are billed again when the items, surcharges or
+    /// contributions of a transaction with surcharges or items change.
+    pub fn apply(&self, transaction: &Transaction) -> Transaction {
+        let mut edited = transaction.clone();
+        if let Some(contributions) = &self.contributions {
+            edited.contributions = contributions.clone();
+        }
+        if let Some(benefits) = &self.benefits {
+            edited.benefits = benefits.clone();
+        }
+        if let Some(description) = &self.description {
+            edited.description = description.clone();
+        }
+        if let Some(datetime) = self.datetime {
+            edited.datetime = datetime;
+        }
+        if let Some(is_direct) = self.is_direct {
+            edited.is_direct = is_direct;
+        }
+        if let Some(items) = &self.items {
+            edited.items = items.clone();
+        }
+        if let Some(surcharges) = &self.surcharges {
+            edited.surcharges = surcharges.clone();
+        }
+        let billing_changed = self.contributions.is_some() || self.items.is_some() || self.surcharges.is_some();
+        let has_surcharges = !edited.items.is_empty() || !edited.surcharges.is_empty() || !transaction.surcharges.is_empty();
+        if billing_changed && has_surcharges {
+            let subtotal = if !edited.items.is_empty() {
+                edited.subtotal()
+            } else if self.contributions.is_some() {
+                // new contributions are given before surcharges, as when adding an expense
+                edited.total_spending()
+            } else {
+                transaction.subtotal()
+            };
+            edited.bill(subtotal);
+        }
+        return edited;
+    }
+}
+
+/// A version of a transaction which was replaced by an edit.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Revision {
+    #[serde(with = "datetime_serialization")]
+    pub edited_at: DateTime<Utc>,
+    pub transaction: Transaction
+}
+
+pub(crate) mod datetime_serialization {
     use serde::{de, Serializer, Deserializer, Deserialize};
     use chrono::{DateTime, Utc};
 
@@ -86,6 +217,13 @@ impl std::fmt::Display for Transaction {
         let dt_string = format!("{}", self.datetime.with_timezone(&Local).format("%F %R %:z"));
         write!(f, "{} ", dt_string.dimmed())?;
 
+        if let Some(currency) = &self.currency {
+            write!(f, "{} ", currency)?;
+            if let Some(rate) = &self.rate {
+                write!(f, "@ {} ", rate)?;
+            }
+        }
+
         write!(f, "{}: ", "From".bold())?;
         for (user, amount) in &self.contributions {
             write!(f, "{}: {}; ", user, amount)?;
@@ -95,8 +233,20 @@ impl std::fmt::Display for Transaction {
         for (user, benefit) in &self.benefits {
             write!(f, "{}: {}; ", user, benefit)?;
         }
+        for item in &self.items {
+            write!(f, "{}; ", item)?;
+        }
+        for surcharge in &self.surcharges {
+            write!(f, "{}; ", surcharge)?;
+        }
 
         write!(f, "{}: {}", "Description".bold(), &self.description)?;
+        if let Some(category) = &self.category {
+            write!(f, "; {}: {}", "Category".bold(), category)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, "; {}: {}", "Tags".bold(), self.tags.join(", "))?;
+        }
         return Ok(());
     }
 }
@@ -111,17 +261,119 @@ impl Transaction {
             None => Utc::now(),
             Some(time) => time
         };
-        let id = match opt_id {
-            None => 0,
-            Some(number) => number
-        };
+        let id = opt_id.unwrap_or_default();
         Transaction {
             id,
             datetime,
             contributions: contributions.to_owned_users(),
             benefits: benefits.to_owned_users(),
             is_direct: direct,
-            description: description.to_string() }
+            description: description.to_string(),
+            remainder: RemainderPolicy::default(),
+            currency: None,
+            rate: None,
+            reverses: None,
+            category: None,
+            tags: Vec::new(),
+            items: Vec::new(),
+            surcharges: Vec::new(),
+            subtotal: None }
+    }
+
+    /// An itemized receipt paid in full by `payer`, who is
+    /// credited with the items plus the surcharges on them.
+    pub fn itemized(payer: &str, items: Vec<Item>, surcharges: Vec<Surcharge>,
+        description: &str, opt_time: Option<DateTime<Utc>>) -> Transaction
+    {
+        let mut transaction = Transaction::new(vec![(payer, Amount::ZERO)], vec![], description, false, None, opt_time);
+        transaction.items = items;
+        transaction.surcharges = surcharges;
+        transaction.bill(transaction.subtotal());
+        return transaction;
+    }
+
+    /// Adds surcharges on top of what was paid. The contributors pay
+    /// them in proportion to how much each of them contributed.
+    pub fn add_surcharges(&mut self, surcharges: Vec<Surcharge>) {
+        let subtotal = self.subtotal();
+        self.surcharges.extend(surcharges);
+        self.bill(subtotal);
+    }
+
+    /// What the transaction came to before surcharges: the sum of the
+    /// items of a receipt, or else worked back from the total spending.
+    pub fn subtotal(&self) -> Amount {
+        if !self.items.is_empty() {
+            return self.items.iter().map(|item| item.price).sum();
+        }
+        if let Some(subtotal) = self.subtotal {
+            return subtotal;
+        }
+        // only for transactions recorded before the subtotal was kept
+        return receipt::subtotal_before(&self.surcharges, self.total_spending());
+    }
+
+    /// Sets the contributions to add up to `subtotal` with the surcharges
+    /// on top, keeping the proportions in which the users paid.
+    fn bill(&mut self, subtotal: Amount) {
+        self.subtotal = if self.items.is_empty() && !self.surcharges.is_empty() { Some(subtotal) } else { None };
+        let total = subtotal + receipt::surcharge_total(&self.surcharges, subtotal);
+        let mut weights: Vec<u64> = self.contributions.iter()
+            .map(|(_, amount)| amount.minor().max(0) as u64).collect();
+        if weights.iter().all(|weight| *weight == 0) {
+            weights.fill(1);
+        }
+        let (shares, _) = allocation::split(total, &weights, RemainderPolicy::RoundRobin);
+        for ((_, amount), share) in self.contributions.iter_mut().zip(shares) {
+            *amount = share;
+        }
+    }
+
+    pub fn contributions(&self) -> &AmountPerUser<UserId> {
+        return &self.contributions;
+    }
+
+    pub fn benefits(&self) -> &BenefitPerUser<UserId> {
+        return &self.benefits;
+    }
+
+    /// All users who contributed to or benefitted from the transaction.
+    pub fn users(&self) -> impl Iterator<Item = &UserId> {
+        return self.contributions.iter().map(|(user, _)| user)
+            .chain(self.benefits.iter().map(|(user, _)| user))
+            .chain(self.items.iter().flat_map(|item| item.beneficiaries.iter()));
+    }
+
+    pub fn involves(&self, user: &str) -> bool {
+        return self.users().any(|u| u == user);
+    }
+
+    pub fn is_paid_by(&self, user: &str) -> bool {
+        return self.contributions.iter().any(|(u, _)| u == user);
+    }
+
+    pub fn benefits_user(&self, user: &str) -> bool {
+        return self.benefits.iter().any(|(u, _)| u == user)
+            || self.items.iter().any(|item| item.beneficiaries.iter().any(|u| u == user));
+    }
+
+    /// Replaces every mention of the user `old` with `new`.
+    pub fn rename_user(&mut self, old: &str, new: &str) {
+        for (user, _) in &mut self.contributions {
+            if user == old {
+                *user = new.to_owned();
+            }
+        }
+        for (user, _) in &mut self.benefits {
+            if user == old {
+                *user = new.to_owned();
+            }
+        }
+        for user in self.items.iter_mut().flat_map(|item| item.beneficiaries.iter_mut()) {
+            if user == old {
+                *user = new.to_owned();
+            }
+        }
     }
 
     pub fn total_spending(&self) -> Amount {
@@ -129,15 +381,11 @@ impl Transaction {
             .map(|contrib| contrib.1).sum();
     }
 
+    /// A transaction which cancels out this one. It is of the same kind,
+    /// either a transfer or an expense, and refers back to this one.
+    /// Items and surcharges are resolved to the exact amounts they came to.
     pub fn reverse(&self) -> TransactionResult<Transaction> {
-        let benefit_per_even = self.benefits_per_even()?;
-
-        let contributions = self.benefits.iter().map(|(user, benefit)| {
-            match benefit {
-                Benefit::Sum(number) => (user.clone(), *number),
-                Benefit::Even => (user.clone(), benefit_per_even)
-            }
-        }).collect();
+        let contributions = self.benefit_amounts()?;
 
         let benefits = self.contributions.iter()
             .map(|(user, contrib)| (user.clone(), Benefit::Sum(*contrib))).collect();
@@ -147,68 +395,245 @@ impl Transaction {
             datetime: Utc::now(),
             contributions,
             benefits,
-            is_direct: false,
-            description: format!("Undo {:04x}", self.id) });
+            is_direct: self.is_direct,
+            description: format!("Undo {:04x}", self.id),
+            remainder: RemainderPolicy::default(),
+            currency: self.currency.clone(),
+            rate: self.rate,
+            reverses: Some(self.id),
+            category: self.category.clone(),
+            tags: self.tags.clone(),
+            items: Vec::new(),
+            surcharges: Vec::new(),
+            subtotal: None });
     }
 
     fn specified_benefits(&self) -> Amount {
         return self.benefits.iter()
             .map(|user_benefit| match user_benefit.1 {
                 Benefit::Sum(val) => val,
-                _ => 0.0
+                _ => Amount::ZERO
             }).sum();
     }
 
-    fn num_even_benefits(&self) -> usize {
+    /// Summed as `u64` so that no number of `u32` percentages can overflow.
+    fn total_percent(&self) -> u64 {
         return self.benefits.iter()
-            .fold(0,|count, user_benefit| match user_benefit.1 {
-                Benefit::Even => count + 1,
-                _ => count
-            });
+            .map(|user_benefit| user_benefit.1.percent() as u64).sum();
     }
 
-    fn benefits_per_even(&self) -> TransactionResult<Amount> {
-        let spending = self.total_spending();
+    fn total_shares(&self) -> u64 {
+        return self.benefits.iter()
+            .map(|user_benefit| user_benefit.1.shares()).sum();
+    }
+
+    /// The user who paid the most, or the first of them if several paid the same.
+    fn main_payer(&self) -> Option<&UserId> {
+        let mut payer: Option<&(UserId, Amount)> = None;
+        for contrib in &self.contributions {
+            match payer {
+                Some(current) if current.1 >= contrib.1 => {},
+                _ => payer = Some(contrib)
+            }
+        }
+        return payer.map(|contrib| &contrib.0);
+    }
+
+    /// Resolves each benefit to the exact amount the user benefitted.
+    /// The amounts always add up to the total spending, with any indivisible
+    /// minor units allocated according to the transaction's `RemainderPolicy`.
+    /// Surcharges are shared in proportion to what each user had before them.
+    pub fn benefit_amounts(&self) -> TransactionResult<AmountPerUser<UserId>> {
+        let subtotal = self.subtotal();
+        let (mut amounts, leftover) = if self.items.is_empty() {
+            self.split_benefits(subtotal)?
+        } else {
+            self.split_items()?
+        };
+
+        let surcharge = self.total_spending() - subtotal;
+        let surcharge_leftover = receipt::spread_proportionally(&mut amounts, surcharge, self.remainder);
+        self.give_to_main_payer(&mut amounts, leftover + surcharge_leftover);
+        return Ok(amounts);
+    }
+
+    /// Splits `spending` according to the benefits. Returns the amount
+    /// per user, and the part which could not be allocated.
+    fn split_benefits(&self, spending: Amount) -> TransactionResult<(AmountPerUser<UserId>, Amount)> {
+        let total_percent = self.total_percent();
+        if total_percent > 100 {
+            return Err(TransactionError::ExcessPercentage{specified: total_percent})
+        }
+
+        // percentages are split off first, with the last weight standing for the rest
+        let mut percent_weights: Vec<u64> = self.benefits.iter()
+            .map(|(_, benefit)| benefit.percent() as u64).collect();
+        percent_weights.push(100 - total_percent);
+        let (mut percent_amounts, percent_leftover) = allocation::split(spending, &percent_weights, self.remainder);
+        let rest = percent_amounts.pop().unwrap_or(Amount::ZERO);
+        let amount_by_percent = spending - rest - percent_leftover;
+
         let specified_benefits = self.specified_benefits();
-        if specified_benefits > spending {
-            return Err(TransactionError::ExcessBenefits{specified: specified_benefits, spent: spending})
+        if specified_benefits > rest {
+            return Err(TransactionError::ExcessBenefits{
+                specified: amount_by_percent + specified_benefits, spent: spending})
+        }
+
+        let total_amount_shared = rest - specified_benefits;
+        if total_amount_shared.is_positive() && self.total_shares() == 0 {
+            if specified_benefits.is_zero() && total_percent > 0 {
+                return Err(TransactionError::InsufficientPercentage{specified: total_percent})
+            }
+            return Err(TransactionError::InsufficientBenefits{
+                specified: amount_by_percent + specified_benefits, spent: spending})
+        }
+
+        let weights: Vec<u64> = self.benefits.iter()
+            .map(|(_, benefit)| benefit.shares()).collect();
+        let (shared_amounts, shared_leftover) = allocation::split(total_amount_shared, &weights, self.remainder);
+        let leftover = percent_leftover + shared_leftover;
+
+        let amounts: AmountPerUser<UserId> = self.benefits.iter()
+            .zip(percent_amounts.into_iter().zip(shared_amounts))
+            .map(|((user, benefit), (by_percent, share))| match benefit {
+                Benefit::Sum(val) => (user.clone(), *val),
+                Benefit::Percent(_) => (user.clone(), by_percent),
+                Benefit::Even | Benefit::Shares(_) => (user.clone(), share)
+            }).collect();
+
+        return Ok((amounts, leftover));
+    }
+
+    /// Splits each item between its beneficiaries. The items with their
+    /// surcharges must add up to exactly the total spending.
+    fn split_items(&self) -> TransactionResult<(AmountPerUser<UserId>, Amount)> {
+        if let Some(item) = self.items.iter().find(|item| item.beneficiaries.is_empty()) {
+            return Err(TransactionError::UnsharedItem(item.description.clone()));
         }
 
-        let num_evens = self.num_even_benefits();
-        let total_amount_evens = spending - specified_benefits;
-        if total_amount_evens > 0.0 && num_evens == 0 {
-            return Err(TransactionError::InsufficientBenefits{specified: specified_benefits, spent: spending})
-        } else if total_amount_evens == 0.0 && num_evens == 0 {
-            return Ok(0.0);
+        let spending = self.total_spending();
+        let subtotal = self.subtotal();
+        let billed = subtotal + receipt::surcharge_total(&self.surcharges, subtotal);
+        if billed < spending {
+            return Err(TransactionError::InsufficientBenefits{specified: billed, spent: spending})
+        }
+        if billed > spending {
+            return Err(TransactionError::ExcessBenefits{specified: billed, spent: spending})
         }
 
-        return Ok(total_amount_evens / (num_evens as f32));
+        return Ok(receipt::item_amounts(&self.items, self.remainder));
+    }
+
+    fn give_to_main_payer(&self, amounts: &mut AmountPerUser<UserId>, leftover: Amount) {
+        if leftover.is_zero() {
+            return;
+        }
+        if let Some(payer) = self.main_payer() {
+            match amounts.iter_mut().find(|(user, _)| user == payer) {
+                Some(entry) => entry.1 += leftover,
+                None => amounts.push((payer.clone(), leftover))
+            }
+        }
     }
 
     pub fn balance_updates(&self) -> TransactionResult<UserAmountMap> {
         let mut balance_delta: UserAmountMap = HashMap::new();
 
-        let benefit_per_even = self.benefits_per_even()?;
-
         for (user, contrib) in &self.contributions {
-            balance_delta.insert(user.clone(), *contrib);
+            *balance_delta.entry(user.clone()).or_insert(Amount::ZERO) += *contrib;
         }
-        for (user, benefit) in &self.benefits {
-            let final_benefit = match *benefit {
-                Benefit::Sum(val) => val,
-                Benefit::Even => benefit_per_even
-            };
-            *balance_delta.entry(user.clone()).or_insert(0f32) -= final_benefit;
+        for (user, benefit) in self.benefit_amounts()? {
+            *balance_delta.entry(user).or_insert(Amount::ZERO) -= benefit;
         }
 
         return Ok(balance_delta);
     }
+
+    /// Money spent by the group, converted at `rate`. Zero for
+    /// direct transfers, and negative for the undo of an expense.
+    pub fn converted_spending(&self, rate: Rate) -> TransactionResult<Amount> {
+        if self.is_direct {
+            return Ok(Amount::ZERO);
+        }
+        let spent = rate.convert(self.total_spending())?;
+        return Ok(if self.reverses.is_some() { -spent } else { spent });
+    }
+
+    /// How much of the spending went to each user, converted at `rate`.
+    /// Empty for direct transfers, and negative for the undo of an expense.
+    /// Always adds up to [`Transaction::converted_spending`].
+    pub fn converted_consumption(&self, rate: Rate) -> TransactionResult<AmountPerUser<UserId>> {
+        if self.is_direct {
+            return Ok(Vec::new());
+        }
+        // an undo swaps the roles, so what was consumed is handed back by the original beneficiaries
+        return match self.reverses {
+            None => self.convert_spending_shares(self.benefit_amounts()?, 1, rate),
+            Some(_) => self.convert_spending_shares(self.contributions.clone(), -1, rate)
+        };
+    }
+
+    /// How much of the spending each user paid, converted at `rate`.
+    /// Empty for direct transfers, and negative for the undo of an expense.
+    /// Always adds up to [`Transaction::converted_spending`].
+    pub fn converted_payments(&self, rate: Rate) -> TransactionResult<AmountPerUser<UserId>> {
+        if self.is_direct {
+            return Ok(Vec::new());
+        }
+        return match self.reverses {
+            None => self.convert_spending_shares(self.contributions.clone(), 1, rate),
+            Some(_) => self.convert_spending_shares(self.benefit_amounts()?, -1, rate)
+        };
+    }
+
+    /// Converts parts of the spending at `rate`, giving rounding differences to the
+    /// largest part so they still add up to [`Transaction::converted_spending`].
+    fn convert_spending_shares(&self, shares: AmountPerUser<UserId>, sign: i64, rate: Rate)
+        -> TransactionResult<AmountPerUser<UserId>> {
+        let mut converted: AmountPerUser<UserId> = shares.into_iter()
+            .map(|(user, amount)| Ok((user, rate.convert(amount)? * sign)))
+            .collect::<TransactionResult<_>>()?;
+
+        let residual = converted.iter().map(|(_, amount)| *amount).sum::<Amount>() - self.converted_spending(rate)?;
+        if !residual.is_zero() {
+            let largest = converted.iter_mut()
+                .max_by(|a, b| a.1.abs().cmp(&b.1.abs()).then_with(|| b.0.cmp(&a.0)));
+            if let Some((_, amount)) = largest {
+                *amount -= residual;
+            }
+        }
+        return Ok(converted);
+    }
+
+    /// Balance updates converted to another currency at `rate`.
+    /// Rounding differences are given to the user with the largest change,
+    /// so that the converted updates still add up to zero.
+    pub fn converted_balance_updates(&self, rate: Rate) -> TransactionResult<UserAmountMap> {
+        let mut converted: UserAmountMap = self.balance_updates()?.into_iter()
+            .map(|(user, delta)| Ok((user, rate.convert(delta)?)))
+            .collect::<TransactionResult<_>>()?;
+
+        let residual: Amount = converted.values().sum();
+        if !residual.is_zero() {
+            let largest = converted.iter()
+                .max_by(|a, b| a.1.abs().cmp(&b.1.abs()).then_with(|| b.0.cmp(a.0)))
+                .map(|(user, _)| user.clone());
+            if let Some(user) = largest {
+                *converted.get_mut(&user).unwrap() -= residual;
+            }
+        }
+
+        return Ok(converted);
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::{Transaction, transaction::Benefit, core::TransactionError};
+    use crate::{Amount, Transaction, transaction::Benefit, core::TransactionError};
+    use crate::allocation::RemainderPolicy;
+    use crate::receipt::{Item, Surcharge};
+    use crate::transaction::TransactionPatch;
     use chrono::{TimeZone, Local, Utc};
     use colored;
     use rstest::{fixture, rstest};
@@ -217,11 +642,11 @@ mod tests {
     fn can_print() {
         colored::control::set_override(false);
 
-        let contrib = vec![("Bilbo", 32.0)];
+        let contrib = vec![("Bilbo", Amount::from_major(32))];
 
         let benefit = vec![
             ("Legolas", Benefit::Even),
-            ("Gimli", Benefit::Sum(10.0))
+            ("Gimli", Benefit::Sum(Amount::from_major(10)))
         ];
 
         let time = Local.ymd(2022, 5, 1).and_hms(12, 0, 0);
@@ -231,20 +656,21 @@ mod tests {
 
         let repr = transaction.to_string();
 
-        assert_eq!(repr, "00d6\t2022-05-01 12:00 +01:00 From: Bilbo: 32; To: Legolas: Even; Gimli: 10; Description: Dinner");
+        let offset = time.format("%:z");
+        assert_eq!(repr, format!("00d6\t2022-05-01 12:00 {} From: Bilbo: 32.00; To: Legolas: Even; Gimli: 10.00; Description: Dinner", offset));
     }
 
     #[fixture]
     fn transaction() -> Transaction {
         let contrib = vec![
-            ("Bilbo", 32.0),
-            ("Frodo", 12.0)
+            ("Bilbo", Amount::from_major(32)),
+            ("Frodo", Amount::from_major(12))
         ];
 
         let benefit = vec![
             ("Legolas", Benefit::Even),
             ("Frodo", Benefit::Even),
-            ("Gimli", Benefit::Sum(10.0))
+            ("Gimli", Benefit::Sum(Amount::from_major(10)))
         ];
 
         let time = Utc.ymd(2022, 5, 1).and_hms(12, 0, 0);
@@ -255,7 +681,7 @@ mod tests {
 
     #[rstest]
     fn total_spent(transaction: Transaction) {
-        assert_eq!(transaction.total_spending(), 44.0);
+        assert_eq!(transaction.total_spending(), Amount::from_major(44));
     }
 
     #[rstest]
@@ -264,21 +690,23 @@ mod tests {
 
         assert_eq!(balance_delta.keys().len(), 4);
 
-        assert_eq!(transaction.num_even_benefits(), 2);
-        assert_eq!(transaction.total_spending(), 44.0);
-        assert_eq!(transaction.specified_benefits(), 10.0);
+        assert_eq!(transaction.total_shares(), 2);
+        assert_eq!(transaction.total_spending(), Amount::from_major(44));
+        assert_eq!(transaction.specified_benefits(), Amount::from_major(10));
 
-        assert_eq!(*balance_delta.get("Bilbo").unwrap(), 32.0);
-        assert_eq!(*balance_delta.get("Legolas").unwrap(), -17.0);
-        assert_eq!(*balance_delta.get("Frodo").unwrap(), -5.0);
-        assert_eq!(*balance_delta.get("Gimli").unwrap(), -10.0);
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_major(32));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_major(-17));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_major(-5));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_major(-10));
     }
 
     #[rstest]
     fn reverse_transaction(transaction: Transaction) {
         let reversed = transaction.reverse().unwrap();
 
-        assert_eq!(reversed.specified_benefits(), 44.0);
+        assert_eq!(reversed.specified_benefits(), Amount::from_major(44));
+        assert_eq!(reversed.reverses, Some(transaction.id));
+        assert_eq!(reversed.is_direct, transaction.is_direct);
 
         let reversed_delta = reversed.balance_updates().unwrap();
         let original_delta = transaction.balance_updates().unwrap();
@@ -286,25 +714,25 @@ mod tests {
 
         for (user, delta) in &reversed_delta {
             assert!(original_delta.contains_key(user));
-            assert_eq!(original_delta.get_key_value(user).unwrap(), (user, &-delta));
+            assert_eq!(original_delta.get_key_value(user).unwrap(), (user, &-*delta));
         }
     }
 
     #[rstest]
     fn insufficient_benefits() {
         let contrib = vec![
-            ("Bilbo", 32.0)
+            ("Bilbo", Amount::from_major(32))
         ];
 
         let benefit = vec![
-            ("Gimli", Benefit::Sum(10.0)),
-            ("Frodo", Benefit::Sum(12.0))
+            ("Gimli", Benefit::Sum(Amount::from_major(10))),
+            ("Frodo", Benefit::Sum(Amount::from_major(12)))
         ];
 
         let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
 
         match result {
-            Err(TransactionError::InsufficientBenefits { specified, spent }) if specified == 22.0 && spent == 32.0 => {},
+            Err(TransactionError::InsufficientBenefits { specified, spent }) if specified == Amount::from_major(22) && spent == Amount::from_major(32) => {},
             _ => panic!("Result does not match InsufficientBenefits: {:?}", &result)
         }
     }
@@ -312,11 +740,11 @@ mod tests {
     #[rstest]
     fn excess_benefits() {
         let contrib = vec![
-            ("Bilbo", 32.0)
+            ("Bilbo", Amount::from_major(32))
         ];
 
         let benefit = vec![
-            ("Gimli", Benefit::Sum(40.0)),
+            ("Gimli", Benefit::Sum(Amount::from_major(40))),
             ("Frodo", Benefit::Even),
             ("Legolas", Benefit::Even)
         ];
@@ -324,7 +752,7 @@ mod tests {
         let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
 
         match result {
-            Err(TransactionError::ExcessBenefits { specified, spent }) if specified == 40.0 && spent == 32.0 => {},
+            Err(TransactionError::ExcessBenefits { specified, spent }) if specified == Amount::from_major(40) && spent == Amount::from_major(32) => {},
             _ => panic!("Result does not match ExcessBenefits: {:?}", &result)
         }
     }
@@ -332,21 +760,333 @@ mod tests {
     #[rstest]
     fn no_evens() {
         let contrib = vec![
-            ("Bilbo", 32.0)
+            ("Bilbo", Amount::from_major(32))
         ];
 
         let benefit = vec![
-            ("Gimli", Benefit::Sum(22.0)),
-            ("Frodo", Benefit::Sum(10.0))
+            ("Gimli", Benefit::Sum(Amount::from_major(22))),
+            ("Frodo", Benefit::Sum(Amount::from_major(10)))
         ];
 
         let balance_delta = Transaction::new(contrib, benefit, "", false, None, None).balance_updates().unwrap();
 
-        assert_eq!(*balance_delta.get("Bilbo").unwrap(), 32.0);
-        assert_eq!(*balance_delta.get("Gimli").unwrap(), -22.0);
-        assert_eq!(*balance_delta.get("Frodo").unwrap(), -10.0);
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_major(32));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_major(-22));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_major(-10));
         assert!(!balance_delta.contains_key("Legolas"));
     }
 
-    // TODO: test reverse
+    #[rstest]
+    fn weighted_shares() {
+        let contrib = vec![("Frodo", Amount::from_major(30))];
+        let benefit = vec![
+            ("Legolas", Benefit::Sum(Amount::from_major(6))),
+            ("Frodo", Benefit::Shares(2)),
+            ("Gimli", Benefit::Even)
+        ];
+        let transaction = Transaction::new(contrib, benefit, "", false, None, None);
+
+        let balance_delta = transaction.balance_updates().unwrap();
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_major(-6));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_major(14));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_major(-8));
+
+        let reversed_delta = transaction.reverse().unwrap().balance_updates().unwrap();
+        for (user, delta) in &reversed_delta {
+            assert_eq!(*balance_delta.get(user).unwrap(), -*delta);
+        }
+    }
+
+    #[rstest]
+    fn zero_shares_insufficient() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![("Gimli", Benefit::Shares(0))];
+
+        let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
+        assert!(matches!(result, Err(TransactionError::InsufficientBenefits { .. })));
+    }
+
+    #[rstest]
+    fn percentages() {
+        let contrib = vec![("Bilbo", Amount::from_minor(1001))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(60)),
+            ("Gimli", Benefit::Percent(40))
+        ];
+        let transaction = Transaction::new(contrib, benefit, "", false, None, None);
+
+        let balance_delta = transaction.balance_updates().unwrap();
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_minor(-601));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_minor(-400));
+    }
+
+    #[rstest]
+    fn percentages_with_sums_and_evens() {
+        let contrib = vec![("Bilbo", Amount::from_major(50))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(20)),
+            ("Gimli", Benefit::Sum(Amount::from_major(4))),
+            ("Legolas", Benefit::Even),
+            ("Bilbo", Benefit::Even)
+        ];
+        let transaction = Transaction::new(contrib, benefit, "", false, None, None);
+
+        let balance_delta = transaction.balance_updates().unwrap();
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_major(-10));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_major(-4));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_major(-18));
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_major(32));
+    }
+
+    #[rstest]
+    fn excess_percentage() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(60)),
+            ("Gimli", Benefit::Percent(50))
+        ];
+
+        let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
+        assert!(matches!(result, Err(TransactionError::ExcessPercentage { specified: 110 })));
+    }
+
+    #[rstest]
+    fn huge_percentages_do_not_overflow() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(u32::MAX - 99)),
+            ("Gimli", Benefit::Percent(200))
+        ];
+
+        let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
+        assert!(matches!(result, Err(TransactionError::ExcessPercentage { specified: 4294967396 })));
+    }
+
+    #[rstest]
+    fn insufficient_percentage() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(60)),
+            ("Gimli", Benefit::Percent(30))
+        ];
+
+        let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
+        assert!(matches!(result, Err(TransactionError::InsufficientPercentage { specified: 90 })));
+    }
+
+    #[rstest]
+    fn percentages_leave_too_little_for_sums() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Percent(80)),
+            ("Gimli", Benefit::Sum(Amount::from_major(3)))
+        ];
+
+        let result = Transaction::new(contrib, benefit, "", false, None, None).balance_updates();
+        match result {
+            Err(TransactionError::ExcessBenefits { specified, spent })
+                if specified == Amount::from_major(11) && spent == Amount::from_major(10) => {},
+            _ => panic!("Result does not match ExcessBenefits: {:?}", &result)
+        }
+    }
+
+    #[rstest]
+    fn converted_updates_sum_to_zero() {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Even),
+            ("Legolas", Benefit::Even),
+            ("Gimli", Benefit::Even)
+        ];
+        let transaction = Transaction::new(contrib, benefit, "", false, None, None);
+
+        let converted = transaction.converted_balance_updates("0.5".parse().unwrap()).unwrap();
+        assert_eq!(converted.values().sum::<Amount>(), Amount::ZERO);
+        assert_eq!(*converted.get("Legolas").unwrap(), Amount::from_minor(-167));
+        // the three rounded shares overshoot by a cent, which the largest change absorbs
+        assert_eq!(*converted.get("Bilbo").unwrap(), Amount::from_minor(501));
+    }
+
+    #[rstest]
+    fn converted_consumption_adds_up() {
+        let transaction = three_way_split(RemainderPolicy::RoundRobin);
+        let rate = "0.5".parse().unwrap();
+
+        let consumed = transaction.converted_consumption(rate).unwrap();
+        assert_eq!(consumed.iter().map(|(_, amount)| *amount).sum::<Amount>(), Amount::from_major(5));
+        assert_eq!(consumed[0], ("Frodo".to_string(), Amount::from_minor(166)));
+        assert_eq!(consumed[1], ("Legolas".to_string(), Amount::from_minor(167)));
+
+        let undone = transaction.reverse().unwrap().converted_consumption(rate).unwrap();
+        assert_eq!(undone.iter().map(|(_, amount)| *amount).sum::<Amount>(), Amount::from_major(-5));
+        assert!(undone.iter().all(|(user, _)| user != "Bilbo"));
+    }
+
+    fn three_way_split(policy: RemainderPolicy) -> Transaction {
+        let contrib = vec![("Bilbo", Amount::from_major(10))];
+        let benefit = vec![
+            ("Frodo", Benefit::Even),
+            ("Legolas", Benefit::Even),
+            ("Gimli", Benefit::Even)
+        ];
+        let mut transaction = Transaction::new(contrib, benefit, "", false, None, None);
+        transaction.remainder = policy;
+        return transaction;
+    }
+
+    #[rstest]
+    #[case(RemainderPolicy::RoundRobin)]
+    #[case(RemainderPolicy::PayerAbsorbs)]
+    #[case(RemainderPolicy::LargestRemainder)]
+    #[case(RemainderPolicy::Random { seed: 7 })]
+    fn indivisible_split_sums_to_zero(#[case] policy: RemainderPolicy) {
+        let balance_delta = three_way_split(policy).balance_updates().unwrap();
+        let total: Amount = balance_delta.values().sum();
+        assert_eq!(total, Amount::ZERO);
+    }
+
+    #[rstest]
+    fn round_robin_remainder() {
+        let balance_delta = three_way_split(RemainderPolicy::RoundRobin).balance_updates().unwrap();
+
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_major(10));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_minor(-334));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_minor(-333));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_minor(-333));
+    }
+
+    #[rstest]
+    fn payer_absorbs_remainder() {
+        let balance_delta = three_way_split(RemainderPolicy::PayerAbsorbs).balance_updates().unwrap();
+
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_minor(999));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_minor(-333));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_minor(-333));
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_minor(-333));
+    }
+
+    #[rstest]
+    fn reverse_indivisible_split() {
+        let transaction = three_way_split(RemainderPolicy::Random { seed: 3 });
+        let original_delta = transaction.balance_updates().unwrap();
+        let reversed_delta = transaction.reverse().unwrap().balance_updates().unwrap();
+
+        for (user, delta) in &reversed_delta {
+            assert_eq!(*original_delta.get(user).unwrap(), -*delta);
+        }
+    }
+
+    fn item(description: &str, price: i64, beneficiaries: &[&str]) -> Item {
+        Item {
+            description: description.to_string(),
+            price: Amount::from_major(price),
+            beneficiaries: beneficiaries.iter().map(|user| user.to_string()).collect()
+        }
+    }
+
+    #[fixture]
+    fn receipt() -> Transaction {
+        let items = vec![
+            item("steak", 45, &["Gimli"]),
+            item("wine", 30, &["Frodo", "Legolas"]),
+            item("bread", 25, &["Frodo", "Legolas", "Gimli", "Bilbo"])
+        ];
+        let surcharges = vec![
+            Surcharge::new("Tax", "10%".parse().unwrap()),
+            Surcharge::new("Discount", "-6".parse().unwrap())
+        ];
+        return Transaction::itemized("Bilbo", items, surcharges, "Dinner", None);
+    }
+
+    #[rstest]
+    fn itemized_receipt(receipt: Transaction) {
+        assert_eq!(receipt.total_spending(), Amount::from_major(104));
+        assert!(receipt.benefits_user("Legolas"));
+
+        // Gimli's items come to 51.25 of the 100, so Gimli takes that share of the net 4.00 surcharge
+        let balance_delta = receipt.balance_updates().unwrap();
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_major(-53) - Amount::from_minor(30));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_minor(-2210));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_minor(-2210));
+        assert_eq!(*balance_delta.get("Bilbo").unwrap(), Amount::from_minor(10400 - 650));
+        assert_eq!(balance_delta.values().sum::<Amount>(), Amount::ZERO);
+    }
+
+    #[rstest]
+    fn reverse_itemized_receipt(receipt: Transaction) {
+        let original_delta = receipt.balance_updates().unwrap();
+        let reversed = receipt.reverse().unwrap();
+        assert!(reversed.items.is_empty());
+
+        for (user, delta) in &reversed.balance_updates().unwrap() {
+            assert_eq!(*original_delta.get(user).unwrap(), -*delta);
+        }
+    }
+
+    #[rstest]
+    fn receipt_must_match_payment(mut receipt: Transaction) {
+        receipt.surcharges.clear();
+        assert!(matches!(receipt.balance_updates(), Err(TransactionError::InsufficientBenefits { .. })));
+
+        receipt.items[0].beneficiaries.clear();
+        assert!(matches!(receipt.balance_updates(), Err(TransactionError::UnsharedItem(..))));
+    }
+
+    #[rstest]
+    fn edit_receipt_rebills_payer(receipt: Transaction) {
+        let patch = TransactionPatch { surcharges: Some(vec![]), ..Default::default() };
+        let edited = patch.apply(&receipt);
+
+        assert_eq!(edited.total_spending(), Amount::from_major(100));
+        assert_eq!(*edited.balance_updates().unwrap().get("Gimli").unwrap(), Amount::from_minor(-5125));
+    }
+
+    #[fixture]
+    fn tipped(transaction: Transaction) -> Transaction {
+        let mut tipped = transaction;
+        tipped.add_surcharges(vec![Surcharge::new("Tip", "25%".parse().unwrap())]);
+        return tipped;
+    }
+
+    #[rstest]
+    fn expense_with_surcharges(tipped: Transaction) {
+        // the 11.00 tip is paid in proportion to the 32 and 12 contributed
+        assert_eq!(tipped.total_spending(), Amount::from_major(55));
+        assert_eq!(tipped.subtotal(), Amount::from_major(44));
+        assert_eq!(tipped.contributions[0], ("Bilbo".to_string(), Amount::from_major(40)));
+
+        let balance_delta = tipped.balance_updates().unwrap();
+        assert_eq!(*balance_delta.get("Gimli").unwrap(), Amount::from_minor(-1250));
+        assert_eq!(*balance_delta.get("Legolas").unwrap(), Amount::from_minor(-2125));
+        assert_eq!(*balance_delta.get("Frodo").unwrap(), Amount::from_minor(1500 - 2125));
+    }
+
+    #[rstest]
+    fn reverse_expense_with_surcharges(tipped: Transaction) {
+        let original_delta = tipped.balance_updates().unwrap();
+        let reversed = tipped.reverse().unwrap();
+        assert!(reversed.surcharges.is_empty());
+
+        for (user, delta) in &reversed.balance_updates().unwrap() {
+            assert_eq!(*original_delta.get(user).unwrap(), -*delta);
+        }
+    }
+
+    #[rstest]
+    fn edit_surcharges(tipped: Transaction) {
+        let patch = TransactionPatch { surcharges: Some(vec![Surcharge::new("Tax", "5".parse().unwrap())]), ..Default::default() };
+        let edited = patch.apply(&tipped);
+
+        assert_eq!(edited.total_spending(), Amount::from_major(49));
+        assert_eq!(edited.subtotal(), Amount::from_major(44));
+    }
+
+    #[rstest]
+    fn full_discount(transaction: Transaction) {
+        let mut free = transaction;
+        free.add_surcharges(vec![Surcharge::new("Discount", "-100%".parse().unwrap())]);
+        assert!(free.total_spending().is_zero());
+        assert_eq!(free.subtotal(), Amount::from_major(44));
+        assert!(free.balance_updates().unwrap().values().all(|delta| delta.is_zero()));
+    }
 }
@@ -1,18 +1,36 @@
 use std::fmt;
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
 pub type UserName = String;
-pub type Amount = f32;
 
+/// Opaque identifier of a user, which stays the same when the user is renamed.
+/// Randomly generated, so that ledgers edited on different devices don't clash.
+pub type UserId = String;
 
-#[derive(Eq, Hash, PartialEq, Serialize, Deserialize)]
+/// Creates a new random user id: a version 4 UUID written as 32 hex digits.
+pub fn generate_user_id() -> UserId {
+    return Uuid::new_v4().simple().to_string();
+}
+
+#[derive(Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct User {
-    pub name: UserName
+    /// Empty only for users read from ledgers which predate user ids
+    #[serde(default)]
+    pub id: UserId,
+    pub name: UserName,
+    /// Inactive users are kept for the history, but can't take part in new transactions
+    #[serde(default = "default_active")]
+    pub active: bool
+}
+
+fn default_active() -> bool {
+    true
 }
 
 impl User {
     pub fn new(name: &str) -> User {
-        User { name: name.to_owned() }
+        User { id: generate_user_id(), name: name.to_owned(), active: true }
     }
 }
 
@@ -39,6 +57,15 @@ mod tests {
         assert_eq!(user.to_string(), "Pinocchio");
     }
 
+    #[test]
+    fn unique_ids() {
+        let first = User::new("Pinocchio");
+        let second = User::new("Pinocchio");
+        assert_eq!(first.id.len(), 32);
+        assert_ne!(first.id, second.id);
+        assert_ne!(first, second);
+    }
+
     #[test]
     fn can_debug() {
         let user = User::new("Pinocchio");
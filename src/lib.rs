@@ -1,5 +1,7 @@
+#![allow(clippy::needless_return)]
+
 mod core;
 pub mod backend;
 
-pub use crate::core::{Ledger, Transaction, User, UserName, Amount};
-pub use crate::core::{ledger, transaction, user, error};
+pub use crate::core::{Ledger, Transaction, User, UserId, UserName, Amount, TransactionError};
+pub use crate::core::{amount, allocation, currency, rates, event, ledger, query, receipt, recurring, report, settlement, transaction, user, error};